@@ -0,0 +1,234 @@
+//! Blending of pixel shader output with the color already stored in the framebuffer.
+//!
+//! Every tile goes through its triangles in submission order, so blending gives the same
+//! result no matter in which order the workers finish.
+use cgmath::*;
+
+
+/// Value the source or destination color is multiplied by before the blend operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+    /// `BlendState::constant` color.
+    Constant,
+    OneMinusConstant,
+    /// `min(src.a, 1 - dst.a)` for color channels and 1 for alpha.
+    SrcAlphaSaturate,
+}
+
+/// Operation combining source and destination after they were multiplied by their factors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendOp {
+    /// `src + dst`
+    Add,
+    /// `src - dst`
+    Subtract,
+    /// `dst - src`
+    ReverseSubtract,
+    /// Per channel minimum, ignoring the factors.
+    Min,
+    /// Per channel maximum, ignoring the factors.
+    Max,
+}
+
+/// How pixel shader output is combined with the framebuffer.
+///
+/// Color channels and alpha are blended separately, so they can use different factors and
+/// operations. With `enable` set to false the output simply replaces the stored color.
+#[derive(Debug, Clone, Copy)]
+pub struct BlendState {
+    pub enable: bool,
+    pub color_src: BlendFactor,
+    pub color_dst: BlendFactor,
+    pub color_op: BlendOp,
+    pub alpha_src: BlendFactor,
+    pub alpha_dst: BlendFactor,
+    pub alpha_op: BlendOp,
+    pub constant: Vector4<f32>,
+    /// Write only as many samples of multisampled pixel as is the share given by the pixel
+    /// shader output alpha, independently of `enable`.
+    pub alpha_to_coverage: bool,
+}
+
+impl Default for BlendState {
+    fn default() -> BlendState {
+        BlendState {
+            enable: false,
+            color_src: BlendFactor::One,
+            color_dst: BlendFactor::Zero,
+            color_op: BlendOp::Add,
+            alpha_src: BlendFactor::One,
+            alpha_dst: BlendFactor::Zero,
+            alpha_op: BlendOp::Add,
+            constant: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            alpha_to_coverage: false,
+        }
+    }
+}
+
+impl BlendState {
+    /// Classic transparency for colors which are not multiplied by their alpha.
+    pub fn alpha() -> BlendState {
+        BlendState {
+            enable: true,
+            color_src: BlendFactor::SrcAlpha,
+            color_dst: BlendFactor::OneMinusSrcAlpha,
+            alpha_src: BlendFactor::One,
+            alpha_dst: BlendFactor::OneMinusSrcAlpha,
+            ..BlendState::default()
+        }
+    }
+
+    /// Transparency for pixel shaders returning colors already multiplied by their alpha.
+    pub fn premultiplied() -> BlendState {
+        BlendState {
+            enable: true,
+            color_src: BlendFactor::One,
+            color_dst: BlendFactor::OneMinusSrcAlpha,
+            alpha_src: BlendFactor::One,
+            alpha_dst: BlendFactor::OneMinusSrcAlpha,
+            ..BlendState::default()
+        }
+    }
+
+    /// Add source to the destination weighted by the source alpha, useful for particles.
+    pub fn additive() -> BlendState {
+        BlendState {
+            enable: true,
+            color_src: BlendFactor::SrcAlpha,
+            color_dst: BlendFactor::One,
+            alpha_src: BlendFactor::Zero,
+            alpha_dst: BlendFactor::One,
+            ..BlendState::default()
+        }
+    }
+
+    /// Combine `src` color from the pixel shader with `dst` from the framebuffer.
+    pub fn blend(&self, src: Vector4<f32>, dst: Vector4<f32>) -> Vector4<f32> {
+        if !self.enable {
+            return src;
+        }
+        let src_color = src.truncate();
+        let dst_color = dst.truncate();
+        let color_src = self.color_factor(self.color_src, src, dst).mul_element_wise(src_color);
+        let color_dst = self.color_factor(self.color_dst, src, dst).mul_element_wise(dst_color);
+        let alpha_src = self.alpha_factor(self.alpha_src, src, dst) * src.w;
+        let alpha_dst = self.alpha_factor(self.alpha_dst, src, dst) * dst.w;
+
+        let color = match self.color_op {
+            BlendOp::Add => color_src + color_dst,
+            BlendOp::Subtract => color_src - color_dst,
+            BlendOp::ReverseSubtract => color_dst - color_src,
+            BlendOp::Min => {
+                Vector3::new(
+                    src_color.x.min(dst_color.x),
+                    src_color.y.min(dst_color.y),
+                    src_color.z.min(dst_color.z),
+                )
+            }
+            BlendOp::Max => {
+                Vector3::new(
+                    src_color.x.max(dst_color.x),
+                    src_color.y.max(dst_color.y),
+                    src_color.z.max(dst_color.z),
+                )
+            }
+        };
+        let alpha = match self.alpha_op {
+            BlendOp::Add => alpha_src + alpha_dst,
+            BlendOp::Subtract => alpha_src - alpha_dst,
+            BlendOp::ReverseSubtract => alpha_dst - alpha_src,
+            BlendOp::Min => src.w.min(dst.w),
+            BlendOp::Max => src.w.max(dst.w),
+        };
+        color.extend(alpha)
+    }
+
+    fn color_factor(&self, factor: BlendFactor, src: Vector4<f32>, dst: Vector4<f32>) -> Vector3<f32> {
+        let one = Vector3::new(1.0, 1.0, 1.0);
+        let splat = |v: f32| Vector3::new(v, v, v);
+        match factor {
+            BlendFactor::Zero => splat(0.0),
+            BlendFactor::One => one,
+            BlendFactor::SrcColor => src.truncate(),
+            BlendFactor::OneMinusSrcColor => one - src.truncate(),
+            BlendFactor::DstColor => dst.truncate(),
+            BlendFactor::OneMinusDstColor => one - dst.truncate(),
+            BlendFactor::SrcAlpha => splat(src.w),
+            BlendFactor::OneMinusSrcAlpha => splat(1.0 - src.w),
+            BlendFactor::DstAlpha => splat(dst.w),
+            BlendFactor::OneMinusDstAlpha => splat(1.0 - dst.w),
+            BlendFactor::Constant => self.constant.truncate(),
+            BlendFactor::OneMinusConstant => one - self.constant.truncate(),
+            BlendFactor::SrcAlphaSaturate => splat(src.w.min(1.0 - dst.w)),
+        }
+    }
+
+    fn alpha_factor(&self, factor: BlendFactor, src: Vector4<f32>, dst: Vector4<f32>) -> f32 {
+        match factor {
+            BlendFactor::Zero => 0.0,
+            BlendFactor::One | BlendFactor::SrcAlphaSaturate => 1.0,
+            BlendFactor::SrcColor | BlendFactor::SrcAlpha => src.w,
+            BlendFactor::OneMinusSrcColor | BlendFactor::OneMinusSrcAlpha => 1.0 - src.w,
+            BlendFactor::DstColor | BlendFactor::DstAlpha => dst.w,
+            BlendFactor::OneMinusDstColor | BlendFactor::OneMinusDstAlpha => 1.0 - dst.w,
+            BlendFactor::Constant => self.constant.w,
+            BlendFactor::OneMinusConstant => 1.0 - self.constant.w,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector4<f32>, b: Vector4<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_blend_disabled() {
+        let src = Vector4::new(0.2, 0.4, 0.6, 0.5);
+        let dst = Vector4::new(1.0, 1.0, 1.0, 1.0);
+        assert_close(BlendState::default().blend(src, dst), src);
+    }
+
+    #[test]
+    fn test_blend_alpha() {
+        let src = Vector4::new(1.0, 0.0, 0.0, 0.25);
+        let dst = Vector4::new(0.0, 0.0, 1.0, 1.0);
+        let blended = BlendState::alpha().blend(src, dst);
+        assert_close(blended, Vector4::new(0.25, 0.0, 0.75, 1.0));
+
+        // Same color premultiplied by its alpha gives the same result.
+        let premultiplied = Vector4::new(0.25, 0.0, 0.0, 0.25);
+        assert_close(BlendState::premultiplied().blend(premultiplied, dst), blended);
+    }
+
+    #[test]
+    fn test_blend_separate_alpha() {
+        let state = BlendState {
+            enable: true,
+            color_src: BlendFactor::One,
+            color_dst: BlendFactor::One,
+            color_op: BlendOp::ReverseSubtract,
+            alpha_src: BlendFactor::Zero,
+            alpha_dst: BlendFactor::One,
+            alpha_op: BlendOp::Max,
+            ..BlendState::default()
+        };
+        let src = Vector4::new(0.25, 0.5, 0.0, 0.5);
+        let dst = Vector4::new(1.0, 1.0, 1.0, 0.25);
+        assert_close(state.blend(src, dst), Vector4::new(0.75, 0.5, 1.0, 0.5));
+    }
+}
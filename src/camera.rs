@@ -0,0 +1,174 @@
+//! Camera describing from where and how the scene is viewed.
+use cgmath::*;
+use gl;
+use shaders;
+
+
+/// Kind of projection used by the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel projection with the view volume `height` units tall in world space.
+    Orthographic { height: f32 },
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub position: Vector3<f32>,
+    pub target: Vector3<f32>,
+    pub up: Vector3<f32>,
+    /// Vertical field of view in radians, used only by perspective projection.
+    pub fovy: f32,
+    /// Width of the view divided by its height.
+    pub aspect_ratio: f32,
+    pub clip_near: f32,
+    pub clip_far: f32,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            position: Vector3::new(0.0, 0.0, 3.0),
+            target: Vector3::new(0.0, 0.0, 0.0),
+            up: Vector3::new(0.0, 1.0, 0.0),
+            fovy: (45.0f32).to_radians(),
+            aspect_ratio: 1.0,
+            clip_near: 0.1,
+            clip_far: 100.0,
+            projection: Projection::Perspective,
+        }
+    }
+}
+
+impl Camera {
+    /// Perspective camera at `position` looking at `target`.
+    pub fn perspective(
+        position: Vector3<f32>,
+        target: Vector3<f32>,
+        fovy: f32,
+        aspect_ratio: f32,
+    ) -> Camera {
+        Camera {
+            position: position,
+            target: target,
+            fovy: fovy,
+            aspect_ratio: aspect_ratio,
+            ..Camera::default()
+        }
+    }
+
+    /// Orthographic camera at `position` looking at `target` which sees `height` units of the
+    /// world vertically.
+    pub fn orthographic(
+        position: Vector3<f32>,
+        target: Vector3<f32>,
+        height: f32,
+        aspect_ratio: f32,
+    ) -> Camera {
+        Camera {
+            position: position,
+            target: target,
+            aspect_ratio: aspect_ratio,
+            projection: Projection::Orthographic { height: height },
+            ..Camera::default()
+        }
+    }
+
+    /// Orthographic camera looking at `target` along the isometric (-1, -1, -1) direction from
+    /// `distance` units away.
+    pub fn isometric(target: Vector3<f32>, distance: f32, height: f32, aspect_ratio: f32) -> Camera {
+        let direction = Vector3::new(1.0, 1.0, 1.0).normalize();
+        Camera::orthographic(target + direction * distance, target, height, aspect_ratio)
+    }
+
+    /// Matrix transforming from world space to view space.
+    pub fn view(&self) -> Matrix4<f32> {
+        gl::view_matrix(self.position, self.target, self.up)
+    }
+
+    /// Matrix transforming from view space to clip space.
+    pub fn projection(&self) -> Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective => {
+                gl::projection_matrix(self.fovy, self.aspect_ratio, self.clip_near, self.clip_far)
+            }
+            Projection::Orthographic { height } => {
+                gl::orthographic_matrix(
+                    height * self.aspect_ratio,
+                    height,
+                    self.clip_near,
+                    self.clip_far,
+                )
+            }
+        }
+    }
+
+    /// Direction from the camera target towards the camera.
+    pub fn cam_dir(&self) -> Vector3<f32> {
+        self.position - self.target
+    }
+
+    /// Uniforms for the shaders in `shaders` module with camera and space transformation
+    /// matrices filled in.
+    pub fn uniforms(&self) -> shaders::Uniforms {
+        shaders::Uniforms {
+            view: self.view(),
+            projection: self.projection(),
+            camera: self.position,
+            camera_target: self.target,
+            cam_dir: self.cam_dir(),
+            orthographic: self.projection != Projection::Perspective,
+            ..shaders::Uniforms::default()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ndc_depth(camera: &Camera, distance: f32) -> f32 {
+        let view_point = Vector4::new(0.0, 0.0, -distance, 1.0);
+        let clip = camera.projection() * view_point;
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn test_clip_planes() {
+        let mut camera = Camera::default();
+        camera.clip_near = 0.5;
+        camera.clip_far = 20.0;
+        assert!((ndc_depth(&camera, 0.5) + 1.0).abs() < 1e-4);
+        assert!((ndc_depth(&camera, 20.0) - 1.0).abs() < 1e-4);
+
+        camera.projection = Projection::Orthographic { height: 2.0 };
+        assert!((ndc_depth(&camera, 0.5) + 1.0).abs() < 1e-4);
+        assert!((ndc_depth(&camera, 20.0) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_aspect_ratio() {
+        let camera = Camera::orthographic(
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            2.0,
+            2.0,
+        );
+        // View is twice as wide as tall, so x = 2 is at the right edge while y = 1 at the top.
+        let clip = camera.projection() * camera.view() * Vector4::new(2.0, 1.0, 0.0, 1.0);
+        assert!((clip.x / clip.w - 1.0).abs() < 1e-4);
+        assert!((clip.y / clip.w - 1.0).abs() < 1e-4);
+    }
+    #[test]
+    fn test_uniforms_view_direction() {
+        let target = Vector3::new(0.0, 0.0, 0.0);
+        let uniforms = Camera::isometric(target, 10.0, 2.0, 1.0).uniforms();
+        assert!(uniforms.orthographic);
+        let direction = Vector3::new(1.0, 1.0, 1.0).normalize();
+        assert!((uniforms.cam_dir.normalize() - direction).magnitude() < 1e-5);
+        assert!(!Camera::default().uniforms().orthographic);
+    }
+}
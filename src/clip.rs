@@ -0,0 +1,184 @@
+//! Clipping of primitives against the view frustum in homogeneous clip space.
+//!
+//! Clipping happens before the perspective divide, so it works for vertices behind the camera
+//! (negative `w`) as well as for the ones which are just outside of the viewport.
+use cgmath::*;
+use gl;
+use varying::Varying;
+
+
+/// Frustum planes in clip space. Point `p` is inside of the plane when `dot(plane, p) >= 0`.
+const PLANES: [[f32; 4]; 6] = [
+    [1.0, 0.0, 0.0, 1.0], // Left:   -w <= x
+    [-1.0, 0.0, 0.0, 1.0], // Right:  x <= w
+    [0.0, 1.0, 0.0, 1.0], // Bottom: -w <= y
+    [0.0, -1.0, 0.0, 1.0], // Top:    y <= w
+    [0.0, 0.0, 1.0, 1.0], // Near:   -w <= z
+    [0.0, 0.0, -1.0, 1.0], // Far:    z <= w
+];
+
+
+#[inline]
+fn plane_distance(plane: &[f32; 4], position: Vector4<f32>) -> f32 {
+    plane[0] * position.x + plane[1] * position.y + plane[2] * position.z + plane[3] * position.w
+}
+
+/// Returns true if every vertex lies inside of every frustum plane.
+fn inside_all<T>(polygon: &[gl::VSOutput<T>]) -> bool {
+    PLANES.iter().all(|plane| {
+        polygon.iter().all(|v| plane_distance(plane, v.position) >= 0.0)
+    })
+}
+
+/// Returns true if every vertex lies outside of the same frustum plane.
+fn outside_any<T>(polygon: &[gl::VSOutput<T>]) -> bool {
+    PLANES.iter().any(|plane| {
+        polygon.iter().all(|v| plane_distance(plane, v.position) < 0.0)
+    })
+}
+
+/// Clip convex polygon against all six frustum planes using Sutherland-Hodgman algorithm.
+///
+/// Vertices created on the planes have their varyings interpolated. Returned polygon keeps the
+/// winding of the input one and is either empty or has at least three vertices, which can be
+/// turned back to triangles with `triangle_fan`.
+pub fn clip_polygon<T: Varying>(polygon: &[gl::VSOutput<T>]) -> Vec<gl::VSOutput<T>> {
+    if inside_all(polygon) {
+        return polygon.to_vec();
+    }
+    if outside_any(polygon) {
+        return Vec::new();
+    }
+
+    let mut output: Vec<gl::VSOutput<T>> = polygon.to_vec();
+    for plane in &PLANES {
+        if output.len() < 3 {
+            return Vec::new();
+        }
+        let input = output;
+        output = Vec::with_capacity(input.len() + 1);
+
+        let mut previous = input[input.len() - 1];
+        let mut previous_distance = plane_distance(plane, previous.position);
+        for current in input {
+            let current_distance = plane_distance(plane, current.position);
+            if (previous_distance >= 0.0) != (current_distance >= 0.0) {
+                let t = previous_distance / (previous_distance - current_distance);
+                output.push(previous.lerp(&current, t));
+            }
+            if current_distance >= 0.0 {
+                output.push(current);
+            }
+            previous = current;
+            previous_distance = current_distance;
+        }
+    }
+    if output.len() < 3 {
+        return Vec::new();
+    }
+    output
+}
+
+/// Clip line from `a` to `b` against all six frustum planes, returning the part of it which is
+/// inside or `None` if there's none. Varyings of moved end points are interpolated.
+pub fn clip_line<T: Varying>(
+    a: &gl::VSOutput<T>,
+    b: &gl::VSOutput<T>,
+) -> Option<(gl::VSOutput<T>, gl::VSOutput<T>)> {
+    let (mut t_start, mut t_end) = (0.0f32, 1.0f32);
+    for plane in &PLANES {
+        let a_distance = plane_distance(plane, a.position);
+        let b_distance = plane_distance(plane, b.position);
+        if a_distance < 0.0 && b_distance < 0.0 {
+            return None;
+        }
+        if a_distance < 0.0 {
+            t_start = t_start.max(a_distance / (a_distance - b_distance));
+        } else if b_distance < 0.0 {
+            t_end = t_end.min(a_distance / (a_distance - b_distance));
+        }
+    }
+    if t_start > t_end {
+        return None;
+    }
+    Some((a.lerp(b, t_start), a.lerp(b, t_end)))
+}
+
+/// Returns true if `vertex` lies inside of the frustum, points outside of it are discarded
+/// as a whole.
+pub fn point_inside<T>(vertex: &gl::VSOutput<T>) -> bool {
+    PLANES.iter().all(|plane| plane_distance(plane, vertex.position) >= 0.0)
+}
+
+/// Indices of triangles making up a convex polygon with `vertex_count` vertices.
+pub fn triangle_fan(vertex_count: usize) -> Vec<[usize; 3]> {
+    (1..vertex_count.saturating_sub(1))
+        .map(|i| [0, i, i + 1])
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32, w: f32) -> gl::VSOutput<Vector2<f32>> {
+        gl::VSOutput {
+            position: Vector4::new(x, y, z, w),
+            varyings: Vector2::new(x, y),
+            viewport_index: 0,
+        }
+    }
+
+    #[test]
+    fn test_clip_inside() {
+        let tri = [
+            vertex(-0.5, -0.5, 0.0, 1.0),
+            vertex(0.5, -0.5, 0.0, 1.0),
+            vertex(0.0, 0.5, 0.0, 1.0),
+        ];
+        assert_eq!(clip_polygon(&tri).len(), 3);
+    }
+
+    #[test]
+    fn test_clip_outside() {
+        let tri = [
+            vertex(2.0, 2.0, 0.0, 1.0),
+            vertex(3.0, 2.0, 0.0, 1.0),
+            vertex(2.0, 3.0, 0.0, 1.0),
+        ];
+        assert!(clip_polygon(&tri).is_empty());
+    }
+
+    #[test]
+    fn test_clip_near_plane() {
+        // Last vertex is behind the near plane, so its corner is cut off and we get a quad.
+        let tri = [
+            vertex(-0.5, 0.0, 0.0, 1.0),
+            vertex(0.5, 0.0, 0.0, 1.0),
+            vertex(0.0, 0.0, -3.0, 1.0),
+        ];
+        let clipped = clip_polygon(&tri);
+        assert_eq!(clipped.len(), 4);
+        for v in &clipped {
+            assert!(v.position.z >= -v.position.w - 1e-5);
+            // Attributes are interpolated together with the position.
+            assert!((v.varyings.x - v.position.x).abs() < 1e-5);
+        }
+        assert_eq!(triangle_fan(clipped.len()).len(), 2);
+    }
+
+    #[test]
+    fn test_clip_line() {
+        let a = vertex(-2.0, 0.0, 0.0, 1.0);
+        let b = vertex(0.5, 0.0, 0.0, 1.0);
+        let (start, end) = clip_line(&a, &b).unwrap();
+        assert!((start.position.x + 1.0).abs() < 1e-5);
+        assert!((start.varyings.x + 1.0).abs() < 1e-5);
+        assert_eq!(end.position, b.position);
+
+        let behind = vertex(0.0, 0.0, -3.0, 1.0);
+        assert!(clip_line(&behind, &vertex(0.0, 0.0, -2.0, 1.0)).is_none());
+        assert!(point_inside(&b) && !point_inside(&behind));
+    }
+}
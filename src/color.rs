@@ -1,4 +1,4 @@
-//! Simple color structure.
+//! Simple color structure and conversions between color spaces.
 use cgmath::Vector4;
 use utils;
 
@@ -37,6 +37,151 @@ pub fn v4_as_value(clr: Vector4<f32>) -> u32 {
     utils::tup8_to_32(clr_u8)
 }
 
+/// Unpack u32 value created by `v4_as_value` back to color with 0.0 - 1.0 ranges.
+pub fn value_as_v4(value: u32) -> Vector4<f32> {
+    Vector4::new(
+        ((value >> 16) & 0xff) as f32 / 255.0,
+        ((value >> 8) & 0xff) as f32 / 255.0,
+        (value & 0xff) as f32 / 255.0,
+        ((value >> 24) & 0xff) as f32 / 255.0,
+    )
+}
+
+
+/// Decode sRGB encoded channel value to linear one.
+#[inline]
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode linear channel value as sRGB.
+#[inline]
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+
+thread_local! {
+    /// Linear value of every 8 bit sRGB encoded channel value, computed once per thread.
+    static SRGB8_TO_LINEAR: [f32; 256] = {
+        let mut table = [0.0; 256];
+        for (v, linear) in table.iter_mut().enumerate() {
+            *linear = srgb_to_linear(v as f32 / 255.0);
+        }
+        table
+    };
+}
+
+/// Decode 8 bit sRGB encoded channel value to linear one, without any `powf`.
+#[inline]
+pub fn srgb8_to_linear(v: u8) -> f32 {
+    SRGB8_TO_LINEAR.with(|table| table[v as usize])
+}
+
+/// Encode linear channel value as 8 bit sRGB, rounded to the value whose linear one is the
+/// closest. Binary search over the decode table, so no `powf` is needed either.
+#[inline]
+pub fn linear_to_srgb8(v: f32) -> u8 {
+    SRGB8_TO_LINEAR.with(|table| {
+        let (mut low, mut high) = (0usize, 255usize);
+        while low < high {
+            let mid = (low + high) / 2;
+            if v < (table[mid] + table[mid + 1]) / 2.0 {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        low as u8
+    })
+}
+
+/// Unpack u32 value with sRGB encoded color channels, as written by `v4_as_srgb_value`, to
+/// linear color.
+#[inline]
+pub fn srgb_value_as_v4(value: u32) -> Vector4<f32> {
+    Vector4::new(
+        srgb8_to_linear((value >> 16) as u8),
+        srgb8_to_linear((value >> 8) as u8),
+        srgb8_to_linear(value as u8),
+        ((value >> 24) & 0xff) as f32 / 255.0,
+    )
+}
+
+/// Pack linear color clamped to 0.0 - 1.0 as u32 value with sRGB encoded color channels, in
+/// the order of `v4_as_value`.
+#[inline]
+pub fn v4_as_srgb_value(clr: Vector4<f32>) -> u32 {
+    let alpha = (utils::saturate(clr.w) * 255.0).round() as u8;
+    utils::tup8_to_32((
+        alpha,
+        linear_to_srgb8(clr.x),
+        linear_to_srgb8(clr.y),
+        linear_to_srgb8(clr.z),
+    ))
+}
+
+
+/// Color with linear channels, in which lighting and blending give physically correct results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearRgba(pub Vector4<f32>);
+
+/// Color with sRGB encoded color channels and linear alpha, as stored in most images and
+/// expected by displays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Srgba(pub Vector4<f32>);
+
+impl LinearRgba {
+    pub fn to_srgba(&self) -> Srgba {
+        let c = self.0;
+        Srgba(Vector4::new(
+            linear_to_srgb(c.x),
+            linear_to_srgb(c.y),
+            linear_to_srgb(c.z),
+            c.w,
+        ))
+    }
+}
+
+impl Srgba {
+    pub fn to_linear(&self) -> LinearRgba {
+        let c = self.0;
+        LinearRgba(Vector4::new(
+            srgb_to_linear(c.x),
+            srgb_to_linear(c.y),
+            srgb_to_linear(c.z),
+            c.w,
+        ))
+    }
+}
+
+impl From<Srgba> for LinearRgba {
+    fn from(color: Srgba) -> LinearRgba {
+        color.to_linear()
+    }
+}
+
+impl From<LinearRgba> for Srgba {
+    fn from(color: LinearRgba) -> Srgba {
+        color.to_srgba()
+    }
+}
+
+/// Plain vectors are taken as linear colors, which is what shaders work with.
+impl From<Vector4<f32>> for LinearRgba {
+    fn from(color: Vector4<f32>) -> LinearRgba {
+        LinearRgba(color)
+    }
+}
+
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct Color {
@@ -119,3 +264,32 @@ impl Color {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb() {
+        let linear = LinearRgba(Vector4::new(0.0, 0.5, 1.0, 0.5));
+        let srgb = linear.to_srgba();
+        assert!((srgb.0.y - 0.7354).abs() < 1e-3);
+        // Alpha and the ends of the range are the same in both spaces.
+        assert_eq!((srgb.0.x, srgb.0.w), (0.0, 0.5));
+        assert!((srgb.0.z - 1.0).abs() < 1e-5);
+        let back = LinearRgba::from(srgb);
+        assert!((back.0.y - 0.5).abs() < 1e-5);
+        assert_eq!(LinearRgba::from(linear.0), linear);
+
+        // Table conversions agree with the exact ones.
+        for v in 0..256 {
+            let exact = srgb_to_linear(v as f32 / 255.0);
+            assert_eq!(srgb8_to_linear(v as u8), exact);
+            assert_eq!(linear_to_srgb8(exact), v as u8);
+        }
+        assert_eq!(linear_to_srgb8(-1.0), 0);
+        assert_eq!(linear_to_srgb8(2.0), 255);
+        assert_eq!(v4_as_srgb_value(Vector4::new(0.5, 0.0, 1.0, 0.5)), 0x80bc00ff);
+    }
+}
@@ -0,0 +1,203 @@
+//! Depth and stencil tests, run for every sample before its pixel is written.
+use std::cmp;
+
+
+/// Comparison of a new value against the one already stored in a buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+impl CompareFunc {
+    /// Returns true if `value` passes the comparison against `stored`.
+    #[inline]
+    pub fn compare<T: cmp::PartialOrd>(&self, value: T, stored: T) -> bool {
+        match *self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => value < stored,
+            CompareFunc::Equal => value == stored,
+            CompareFunc::LessEqual => value <= stored,
+            CompareFunc::Greater => value > stored,
+            CompareFunc::NotEqual => value != stored,
+            CompareFunc::GreaterEqual => value >= stored,
+            CompareFunc::Always => true,
+        }
+    }
+}
+
+/// State controlling the depth test and depth buffer writes.
+/// Range of the written depth values is set by `Viewport`.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthState {
+    /// Pixel is shaded only if its depth passes this comparison against the depth buffer.
+    pub func: CompareFunc,
+    /// Write depth of the shaded pixels to the depth buffer.
+    pub write_enable: bool,
+}
+
+impl Default for DepthState {
+    fn default() -> DepthState {
+        DepthState {
+            func: CompareFunc::Less,
+            write_enable: true,
+        }
+    }
+}
+
+/// Operation applied to the stencil buffer value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    /// Replace with `StencilState::reference`.
+    Replace,
+    IncrementClamp,
+    DecrementClamp,
+    Invert,
+    IncrementWrap,
+    DecrementWrap,
+}
+
+impl StencilOp {
+    #[inline]
+    fn apply(&self, value: u8, reference: u8) -> u8 {
+        match *self {
+            StencilOp::Keep => value,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::IncrementClamp => value.saturating_add(1),
+            StencilOp::DecrementClamp => value.saturating_sub(1),
+            StencilOp::Invert => !value,
+            StencilOp::IncrementWrap => value.wrapping_add(1),
+            StencilOp::DecrementWrap => value.wrapping_sub(1),
+        }
+    }
+}
+
+/// Stencil test and operations for triangles of one facing.
+#[derive(Debug, Clone, Copy)]
+pub struct StencilFaceState {
+    /// Sample passes if `reference & read_mask` passes this comparison against the stored
+    /// value masked the same way.
+    pub func: CompareFunc,
+    /// Applied when stencil test fails.
+    pub fail_op: StencilOp,
+    /// Applied when stencil test passes but depth test fails.
+    pub depth_fail_op: StencilOp,
+    /// Applied when both stencil and depth tests pass.
+    pub pass_op: StencilOp,
+}
+
+impl Default for StencilFaceState {
+    fn default() -> StencilFaceState {
+        StencilFaceState {
+            func: CompareFunc::Always,
+            fail_op: StencilOp::Keep,
+            depth_fail_op: StencilOp::Keep,
+            pass_op: StencilOp::Keep,
+        }
+    }
+}
+
+/// State controlling the stencil test and stencil buffer writes.
+#[derive(Debug, Clone, Copy)]
+pub struct StencilState {
+    pub enable: bool,
+    /// Used by front facing triangles, as set by `FrontFace`.
+    pub front: StencilFaceState,
+    /// Used by back facing triangles.
+    pub back: StencilFaceState,
+    pub reference: u8,
+    /// Bits of the reference and stored value used by the stencil test.
+    pub read_mask: u8,
+    /// Bits of the stored value which can be changed by stencil operations.
+    pub write_mask: u8,
+}
+
+impl Default for StencilState {
+    fn default() -> StencilState {
+        StencilState {
+            enable: false,
+            front: StencilFaceState::default(),
+            back: StencilFaceState::default(),
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+        }
+    }
+}
+
+impl StencilState {
+    /// Same test and operations for both front and back facing triangles.
+    pub fn both_faces(face: StencilFaceState, reference: u8) -> StencilState {
+        StencilState {
+            enable: true,
+            front: face,
+            back: face,
+            reference: reference,
+            ..StencilState::default()
+        }
+    }
+
+    /// Face state used for triangles facing the given way.
+    #[inline]
+    pub fn face(&self, is_front_facing: bool) -> &StencilFaceState {
+        if is_front_facing { &self.front } else { &self.back }
+    }
+
+    /// Returns true if the reference passes the stencil test of `face` against `stored`.
+    #[inline]
+    pub fn test(&self, face: &StencilFaceState, stored: u8) -> bool {
+        face.func.compare(self.reference & self.read_mask, stored & self.read_mask)
+    }
+
+    /// Value stored after applying `op` to `stored`, changing only bits in the write mask.
+    #[inline]
+    pub fn update(&self, op: StencilOp, stored: u8) -> u8 {
+        let value = op.apply(stored, self.reference);
+        (stored & !self.write_mask) | (value & self.write_mask)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_func() {
+        assert!(CompareFunc::Less.compare(0.25, 0.5));
+        assert!(!CompareFunc::Less.compare(0.5, 0.5));
+        assert!(CompareFunc::GreaterEqual.compare(0.5, 0.5));
+        assert!(!CompareFunc::Never.compare(0, 1));
+        assert!(CompareFunc::Always.compare(1, 0));
+    }
+
+    #[test]
+    fn test_stencil_masks() {
+        let face = StencilFaceState {
+            func: CompareFunc::Equal,
+            ..StencilFaceState::default()
+        };
+        let state = StencilState {
+            read_mask: 0x0f,
+            write_mask: 0xf0,
+            ..StencilState::both_faces(face, 0x35)
+        };
+        // Only the low bits take part in the test.
+        assert!(state.test(state.face(true), 0xa5));
+        assert!(!state.test(state.face(false), 0x34));
+        // Only the high bits are changed.
+        assert_eq!(state.update(StencilOp::Replace, 0x0a), 0x3a);
+        assert_eq!(state.update(StencilOp::Invert, 0x0a), 0xfa);
+        assert_eq!(state.update(StencilOp::DecrementWrap, 0x00), 0xf0);
+        assert_eq!(state.update(StencilOp::IncrementClamp, 0xff), 0xff);
+    }
+}
@@ -1,31 +1,67 @@
-use std::sync;
+use std::cmp;
+use std::fs;
+use std::io;
+use std::io::Write;
 use std::path;
-use std::thread;
 
 use cgmath::*;
-
+use scoped_threadpool;
+use num_cpus;
 use image;
+
+use blend;
+use depth_stencil::{CompareFunc, DepthState, StencilState};
+use clip;
+use color;
+use target;
+use target::PixelOutput;
 use model;
 use triangle;
 use utils;
-use color;
+use varying::Varying;
 
 
-// TODO: Give this as parametes somewhere.
-const CLIP_FAR: f32 = 99.0;
-const CLIP_NEAR: f32 = 0.0;
+/// Per instance attributes of an instanced draw, passed to the vertex shader with every vertex
+/// of the instance.
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    /// Transformation from model space of the instance to world space.
+    pub model: Matrix4<f32>,
+    pub tint: Vector4<f32>,
+    /// Any other values the vertex shader needs.
+    pub custom: Vector4<f32>,
+}
+
+impl Default for Instance {
+    fn default() -> Instance {
+        Instance::new(
+            Matrix4::identity(),
+            Vector4::new(1.0, 1.0, 1.0, 1.0),
+            Vector4::new(0.0, 0.0, 0.0, 0.0),
+        )
+    }
+}
 
+impl Instance {
+    /// Instance placed by `model` matrix.
+    pub fn new(model: Matrix4<f32>, tint: Vector4<f32>, custom: Vector4<f32>) -> Instance {
+        Instance {
+            model: model,
+            tint: tint,
+            custom: custom,
+        }
+    }
+}
 
+/// Per vertex attributes passed to the vertex shader.
 #[derive(Debug, Clone, Copy)]
 pub struct VSInput {
     pub position: Vector4<f32>,
     pub texcoord: Vector2<f32>,
     pub normal: Vector4<f32>,
-    pub camera: Vector3<f32>,
-    pub camera_target: Vector3<f32>,
-    // Space transformation matrices
-    pub view: Matrix4<f32>,
-    pub projection: Matrix4<f32>,
+    /// Index of the instance the vertex belongs to, always 0 for draws which aren't instanced.
+    pub instance_id: usize,
+    pub instance: Instance,
 }
 
 impl Default for VSInput {
@@ -34,54 +70,295 @@ impl Default for VSInput {
             position: Vector4::new(0.0, 0.0, 0.0, 1.0),
             normal: Vector4::new(1.0, 1.0, 1.0, 0.0),
             texcoord: Vector2::new(0.0, 0.0),
-            camera: Vector3::new(0.0, 0.0, 0.0),
-            camera_target: Vector3::new(0.0, 0.0, 0.0),
-            view: Matrix4::identity(),
-            projection: Matrix4::identity(),
+            instance_id: 0,
+            instance: Instance::default(),
         }
     }
 }
 
+/// Vertex shader output, with clip space `position` and user defined `varyings` which are
+/// interpolated and passed to the pixel shader.
 #[derive(Debug, Clone, Copy)]
-pub struct VSOutput {
+pub struct VSOutput<T> {
     pub position: Vector4<f32>,
-    pub normal: Vector4<f32>,
-    pub texcoord: Vector2<f32>,
+    pub varyings: T,
+    /// Viewport used for the triangle, taken from its first vertex. Indices past the last
+    /// viewport set on `Gl` select the first one.
+    pub viewport_index: usize,
+}
+
+impl<T: Varying> VSOutput<T> {
+    /// Linearly interpolate all outputs between `self` and `other` in clip space.
+    pub fn lerp(&self, other: &VSOutput<T>, t: f32) -> VSOutput<T> {
+        let position = self.position.lerp(other.position, t);
+        // Same point on the line between the two after the perspective divide.
+        let screen_t = if position.w != 0.0 {
+            t * other.position.w / position.w
+        } else {
+            t
+        };
+        VSOutput {
+            position: position,
+            varyings: T::interpolate_screen(
+                &self.varyings,
+                &other.varyings,
+                &self.varyings,
+                Vector3::new(1.0 - t, t, 0.0),
+                Vector3::new(1.0 - screen_t, screen_t, 0.0),
+            ),
+            viewport_index: self.viewport_index,
+        }
+    }
 }
 
-impl Default for VSOutput {
-    fn default() -> VSOutput {
+impl<T: Default> Default for VSOutput<T> {
+    fn default() -> VSOutput<T> {
         VSOutput {
             position: Vector4::new(0.0, 0.0, 0.0, 1.0),
-            normal: Vector4::new(1.0, 1.0, 1.0, 0.0),
-            texcoord: Vector2::new(0.0, 0.0),
+            varyings: T::default(),
+            viewport_index: 0,
         }
     }
 }
 
-#[derive(Clone)]
-pub struct PSInput {
-    pub textures: Vec<sync::Arc<image::DynamicImage>>,
-    pub light_pos: Vector3<f32>,
-    pub cam_dir: Vector3<f32>,
+/// Per pixel inputs passed to the pixel shader.
+#[derive(Debug, Clone, Copy)]
+pub struct PSInput<T> {
+    /// Screen space position of the pixel center, with depth as z.
     pub position: Vector3<f32>,
-    pub normal: Vector3<f32>,
-    pub texcoord: Vector2<f32>,
+    /// True if the rasterized triangle is facing the camera, as set by `FrontFace`.
+    pub is_front_facing: bool,
+    /// Varyings returned by the vertex shader, interpolated for this pixel.
+    pub varyings: T,
+    /// Difference of varyings between the right and left pixel in this pixel's row of its 2x2
+    /// quad, their rate of change along screen x.
+    pub ddx: T,
+    /// Difference of varyings between the bottom and top pixel in this pixel's column of its
+    /// 2x2 quad, their rate of change along screen y.
+    pub ddy: T,
 }
 
-impl Default for PSInput {
-    fn default() -> PSInput {
-        PSInput {
-            textures: Vec::new(),
-            light_pos: Vector3::new(0.0, 0.0, 0.0),
-            cam_dir: Vector3::new(0.0, 0.0, 1.0),
-            position: Vector3::new(0.0, 0.0, 0.0),
-            normal: Vector3::new(1.0, 1.0, 1.0),
-            texcoord: Vector2::new(0.0, 0.0),
+
+/// Shader run for every vertex, transforming it to clip space.
+///
+/// `U` is the uniform block shared by all invocations during a draw. Functions and closures
+/// taking `(&U, &VSInput)` are vertex shaders as well.
+pub trait VertexShader<U>: Sync {
+    type Varyings: Varying;
+
+    fn shade(&self, uniforms: &U, input: &VSInput) -> VSOutput<Self::Varyings>;
+}
+
+impl<U, T, F> VertexShader<U> for F
+where
+    F: Fn(&U, &VSInput) -> VSOutput<T> + Sync,
+    T: Varying,
+{
+    type Varyings = T;
+
+    fn shade(&self, uniforms: &U, input: &VSInput) -> VSOutput<T> {
+        self(uniforms, input)
+    }
+}
+
+/// Shader run for every rasterized pixel, returning values for the render targets.
+///
+/// `U` is the uniform block shared by all invocations during a draw and `T` the varyings
+/// returned by the vertex shader. Functions and closures taking `(&U, &PSInput<T>)` are pixel
+/// shaders as well.
+pub trait PixelShader<U, T>: Sync {
+    /// Single `Vector4<f32>` color, or a struct with value for every render target.
+    type Output: PixelOutput;
+
+    fn shade(&self, uniforms: &U, input: &PSInput<T>) -> Self::Output;
+}
+
+impl<U, T, O, F> PixelShader<U, T> for F
+where
+    F: Fn(&U, &PSInput<T>) -> O + Sync,
+    O: PixelOutput,
+{
+    type Output = O;
+
+    fn shade(&self, uniforms: &U, input: &PSInput<T>) -> O {
+        self(uniforms, input)
+    }
+}
+
+
+/// Which triangles are discarded before rasterization based on their facing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+/// Winding order of front facing triangles as seen on the screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrontFace {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// How vertex shader outputs are interpolated across triangles. Applies to all varyings of a
+/// draw, single ones can be wrapped in `varying::NoPerspective` to always be interpolated
+/// linearly in screen space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    /// Interpolate in view space, taking the perspective divide into account.
+    Perspective,
+    /// Interpolate linearly in screen space, useful for screen space attributes.
+    NoPerspective,
+}
+
+/// What part of the triangles gets rasterized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolygonMode {
+    /// Whole area of triangles.
+    Fill,
+    /// Triangle edges as `RasterizerState::line_width` wide lines.
+    Line,
+    /// Triangle vertices as `RasterizerState::point_size` wide points.
+    Point,
+}
+
+/// State controlling how triangles are turned into pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct RasterizerState {
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    pub interpolation: Interpolation,
+    pub polygon_mode: PolygonMode,
+    /// Precision of vertex positions as number of fractional bits of a pixel, from 1 up to
+    /// `triangle::MAX_SUBPIXEL_BITS`. Draws to large viewports use fewer bits if needed, see
+    /// `triangle::max_subpixel_bits`.
+    pub subpixel_bits: u32,
+    /// Width of lines in pixels.
+    pub line_width: f32,
+    /// Width and height of the square drawn for every point, in pixels.
+    pub point_size: f32,
+    /// Constant offset added to the depth of every primitive.
+    pub depth_bias: f32,
+    /// Offset added to the depth of triangles multiplied by their largest depth change per
+    /// pixel, so steep triangles get moved more.
+    pub slope_scaled_depth_bias: f32,
+}
+
+impl Default for RasterizerState {
+    fn default() -> RasterizerState {
+        RasterizerState {
+            cull_mode: CullMode::None,
+            front_face: FrontFace::CounterClockwise,
+            interpolation: Interpolation::Perspective,
+            polygon_mode: PolygonMode::Fill,
+            subpixel_bits: triangle::DEFAULT_SUBPIXEL_BITS,
+            line_width: 1.0,
+            point_size: 1.0,
+            depth_bias: 0.0,
+            slope_scaled_depth_bias: 0.0,
+        }
+    }
+}
+
+/// Rectangle of pixels with `(x, y)` as its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    /// Part of the rectangle which is also inside of `other`, if there is any.
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = cmp::max(self.x, other.x);
+        let y = cmp::max(self.y, other.y);
+        let right = cmp::min(
+            self.x.saturating_add(self.width),
+            other.x.saturating_add(other.width),
+        );
+        let bottom = cmp::min(
+            self.y.saturating_add(self.height),
+            other.y.saturating_add(other.height),
+        );
+        if x >= right || y >= bottom {
+            None
+        } else {
+            Some(Rect::new(x, y, right - x, bottom - y))
+        }
+    }
+}
+
+/// Part of the framebuffer which normalized device coordinates are mapped to, together with
+/// the range of depth values written for the near and far clip planes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub depth_near: f32,
+    pub depth_far: f32,
+}
+
+impl Viewport {
+    /// Viewport with the default 0 - 1 depth range.
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Viewport {
+        Viewport {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+            depth_near: 0.0,
+            depth_far: 1.0,
         }
     }
+
+    /// Width of the viewport divided by its height.
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.width, self.height)
+    }
+
+    /// Furthest distance from the framebuffer origin in pixels covered by the viewport along
+    /// either axis.
+    fn extent(&self) -> u32 {
+        cmp::max(
+            self.x.saturating_add(self.width),
+            self.y.saturating_add(self.height),
+        )
+    }
 }
 
+/// Matrix transforming normals of a mesh transformed by `model`, so they stay perpendicular to
+/// surfaces under non-uniform scale.
+///
+/// It's the cofactor matrix of the upper 3x3 part, which is its inverse transpose scaled by the
+/// determinant, so it's defined for singular matrices as well. Normals have to be normalized
+/// after the transformation. Mirroring matrices are negated, so normals keep facing out.
+pub fn normal_matrix(model: &Matrix4<f32>) -> Matrix3<f32> {
+    let (a, b, c) = (model.x.truncate(), model.y.truncate(), model.z.truncate());
+    let cofactor = Matrix3::from_cols(b.cross(c), c.cross(a), a.cross(b));
+    if a.dot(b.cross(c)) < 0.0 {
+        -cofactor
+    } else {
+        cofactor
+    }
+}
 
 /// Construct View matrix which transforms from world space to view space.
 pub fn view_matrix(
@@ -105,168 +382,2270 @@ pub fn view_matrix(
     )
 }
 
-pub fn projection_matrix(fovy: f32) -> Matrix4<f32> {
-    let d = 1.0 / (fovy.tan() / 2.0);
-    let aspect_ratio = 1.0;
+/// Construct perspective projection matrix which transforms from view space to clip space.
+/// `fovy` is vertical field of view in radians and `aspect_ratio` is width divided by height.
+pub fn projection_matrix(fovy: f32, aspect_ratio: f32, clip_near: f32, clip_far: f32) -> Matrix4<f32> {
+    let d = 1.0 / (fovy / 2.0).tan();
     let mut projection = Matrix4::identity();
     projection[0][0] = d / aspect_ratio;
     projection[1][1] = d;
-    projection[2][2] = (CLIP_NEAR + CLIP_FAR) / (CLIP_NEAR - CLIP_FAR);
-    projection[3][2] = 2.0 * CLIP_NEAR * CLIP_FAR / (CLIP_NEAR - CLIP_FAR);
+    projection[2][2] = (clip_near + clip_far) / (clip_near - clip_far);
+    projection[3][2] = 2.0 * clip_near * clip_far / (clip_near - clip_far);
     projection[2][3] = -1.0;
+    projection[3][3] = 0.0;
     projection
 }
 
-/// Construct viewport transformation matrix which translates ndc to screen/viewport coordinates.
-pub fn viewport_matrix(
-    viewport_dimensions: (u32, u32),
-    clip_near: f32,
-    clip_far: f32,
-) -> Matrix4<f32> {
-    let mut viewport: Matrix4<f32> = Matrix4::identity();
-    let (viewport_width, viewport_height) = viewport_dimensions;
-    viewport[0][0] = (viewport_width - 1) as f32 / 2.0;
-    viewport[1][1] = -1.0 * (viewport_height - 1) as f32 / 2.0;
-    viewport[2][2] = (clip_far - clip_near) / 2.0;
-    viewport[3][0] = (viewport_width - 1) as f32 / 2.0;
-    viewport[3][1] = (viewport_height - 1) as f32 / 2.0;
-    viewport[3][2] = (clip_near + clip_far) / 2.0;
-    viewport
+/// Construct orthographic projection matrix for view volume of given `width` and `height`
+/// centered on the camera view direction.
+pub fn orthographic_matrix(width: f32, height: f32, clip_near: f32, clip_far: f32) -> Matrix4<f32> {
+    let mut projection = Matrix4::identity();
+    projection[0][0] = 2.0 / width;
+    projection[1][1] = 2.0 / height;
+    projection[2][2] = 2.0 / (clip_near - clip_far);
+    projection[3][2] = (clip_near + clip_far) / (clip_near - clip_far);
+    projection
+}
+
+/// Construct viewport transformation matrix which translates ndc to screen coordinates.
+/// Edges of ndc cube land on the outer edges of viewport pixels, so pixel `(x, y)` has its
+/// center at `(x + 0.5, y + 0.5)`. Depth is mapped from ndc to the viewport depth range.
+pub fn viewport_matrix(viewport: &Viewport) -> Matrix4<f32> {
+    let mut matrix: Matrix4<f32> = Matrix4::identity();
+    let (width, height) = (viewport.width as f32, viewport.height as f32);
+    matrix[0][0] = width / 2.0;
+    matrix[1][1] = -1.0 * height / 2.0;
+    matrix[2][2] = (viewport.depth_far - viewport.depth_near) / 2.0;
+    matrix[3][0] = viewport.x as f32 + width / 2.0;
+    matrix[3][1] = viewport.y as f32 + height / 2.0;
+    matrix[3][2] = (viewport.depth_near + viewport.depth_far) / 2.0;
+    matrix
 }
 
 
-/// Results returned from threads run per face.
-struct FaceThreadResult {
-    pub bi: Vec<usize>, // Buffer index
-    pub fbv: Vec<u32>, // Frame buffer values
-    pub zbv: Vec<f32>, // Z Buffer values
+/// Width and height of the square screen tiles in pixels.
+const TILE_SIZE: u32 = 64;
+
+/// Depth bias added to edges drawn by `Gl::draw_with_wireframe`. Slope scaled part is scaled by
+/// the half of line width by which edges reach past their triangle, covering twice the depth
+/// change over it.
+const WIREFRAME_DEPTH_BIAS: f32 = -1.0e-5;
+const WIREFRAME_SLOPE_SCALED_DEPTH_BIAS: f32 = -2.0;
+
+
+/// Viewport prepared for a draw, with pixels which can be covered as `(min_x, min_y, max_x,
+/// max_y)`, inclusive, or `None` if viewport lies outside of the framebuffer or scissor.
+struct ScreenViewport {
+    matrix: Matrix4<f32>,
+    bounds: Option<(u32, u32, u32, u32)>,
+    /// `Viewport::depth_near` and `depth_far` as `(min, max)`.
+    depth_range: (f32, f32),
 }
 
+impl ScreenViewport {
+    /// Screen space position of clip space `position`, which has to be inside of the frustum.
+    fn to_screen(&self, position: Vector4<f32>) -> Vector3<f32> {
+        let ndc = Vector4::new(
+            position.x / position.w,
+            position.y / position.w,
+            position.z / position.w,
+            1.0,
+        );
+        (self.matrix * ndc).truncate()
+    }
 
-pub struct Gl {
-    viewport_dimensions: (u32, u32),
-    fb: Vec<u32>,
-    fb_width: usize,
+    /// `position` with `depth_bias` added to its depth, kept inside of the viewport depth range.
+    #[inline]
+    fn with_depth_bias(&self, position: Vector3<f32>, depth_bias: f32) -> Vector3<f32> {
+        let (min, max) = self.depth_range;
+        Vector3::new(position.x, position.y, (position.z + depth_bias).max(min).min(max))
+    }
+}
+
+
+/// Triangle after vertex shading, clipping and viewport transformation, ready to be rasterized.
+struct ScreenTriangle<T> {
+    positions: [Vector3<f32>; 3],
+    varyings: [T; 3],
+    /// Per vertex factors applied to barycentric coordinates before interpolating attributes.
+    /// `1/w` of clip space position for perspective correct interpolation, ones otherwise.
+    perspective: [f32; 3],
+    is_front_facing: bool,
+    setup: triangle::TriangleSetup,
+}
+
+impl<T> ScreenTriangle<T> {
+    /// Depth at screen space barycentric coordinates `bary`.
+    #[inline]
+    fn depth(&self, bary: Vector3<f32>) -> f32 {
+        self.positions[0].z * bary.x + self.positions[1].z * bary.y + self.positions[2].z * bary.z
+    }
+}
+
+
+/// Turn screen space barycentric coordinates `bary` to the ones used for interpolating
+/// attributes, by weighting them with per vertex `perspective` factors.
+#[inline]
+fn perspective_correct(bary: Vector3<f32>, perspective: &[f32; 3]) -> Vector3<f32> {
+    let weighted = Vector3::new(
+        bary.x * perspective[0],
+        bary.y * perspective[1],
+        bary.z * perspective[2],
+    );
+    weighted / (weighted.x + weighted.y + weighted.z)
+}
+
+/// Viewport and rasterizer state primitives of one draw are set up with.
+struct PrimitiveTarget<'a> {
+    viewport: &'a ScreenViewport,
+    bounds: (u32, u32, u32, u32),
+    state: &'a RasterizerState,
+    samples: &'a [(i32, i32)],
+}
+
+impl<'a> PrimitiveTarget<'a> {
+    /// Target of primitive whose first vertex selects viewport `viewport_index`, or `None`
+    /// if nothing of that viewport can be drawn.
+    fn new(
+        viewports: &'a [ScreenViewport],
+        viewport_index: usize,
+        state: &'a RasterizerState,
+        samples: &'a [(i32, i32)],
+    ) -> Option<PrimitiveTarget<'a>> {
+        let viewport = viewports.get(viewport_index).unwrap_or(&viewports[0]);
+        viewport.bounds.map(|bounds| {
+            PrimitiveTarget {
+                viewport: viewport,
+                bounds: bounds,
+                state: state,
+                samples: samples,
+            }
+        })
+    }
+
+    /// Set up screen space triangle for rasterization, `None` if it's degenerate or falls
+    /// between samples.
+    fn setup(&self, positions: &[Vector3<f32>; 3]) -> Option<triangle::TriangleSetup> {
+        triangle::TriangleSetup::with_samples(
+            positions,
+            self.state.subpixel_bits,
+            self.bounds,
+            self.samples,
+        )
+    }
+}
+
+/// Clip triangle made of already shaded vertices and push screen space triangles created
+/// for it by `RasterizerState::polygon_mode` to `triangles`, if it survives culling.
+fn process_triangle<T: Varying>(
+    vs_outputs: &[VSOutput<T>],
+    viewports: &[ScreenViewport],
+    rasterizer_state: &RasterizerState,
+    samples: &[(i32, i32)],
+    triangles: &mut Vec<ScreenTriangle<T>>,
+) {
+    let target = match PrimitiveTarget::new(
+        viewports,
+        vs_outputs[0].viewport_index,
+        rasterizer_state,
+        samples,
+    ) {
+        Some(target) => target,
+        None => return,
+    };
+
+    // Clipped polygon has every vertex inside of the frustum, so after the
+    // perspective divide all of them land inside of the viewport.
+    let polygon = clip::clip_polygon(vs_outputs);
+    if polygon.is_empty() {
+        return;
+    }
+
+    let mut polygon_ss: Vec<Vector3<f32>> = polygon
+        .iter()
+        .map(|vs_out| target.viewport.to_screen(vs_out.position))
+        .collect();
+    let depth_bias = rasterizer_state.depth_bias +
+        rasterizer_state.slope_scaled_depth_bias * depth_slope(&polygon_ss);
+    for position in &mut polygon_ss {
+        *position = target.viewport.with_depth_bias(*position, depth_bias);
+    }
+    let polygon_perspective: Vec<f32> = polygon
+        .iter()
+        .map(|vs_out| perspective_factor(rasterizer_state, vs_out))
+        .collect();
+
+    if rasterizer_state.polygon_mode != PolygonMode::Fill {
+        // Whole polygon is culled or kept based on its winding, original triangle edges and
+        // vertices are then drawn instead of its area.
+        let signed_area = (0..polygon_ss.len()).fold(0.0, |area, i| {
+            let (a, b) = (polygon_ss[i], polygon_ss[(i + 1) % polygon_ss.len()]);
+            area + a.x * b.y - b.x * a.y
+        });
+        if signed_area == 0.0 {
+            return;
+        }
+        let is_front_facing = is_front_facing(rasterizer_state, signed_area < 0.0);
+        if is_culled(rasterizer_state, is_front_facing) {
+            return;
+        }
+        for i in 0..3 {
+            if rasterizer_state.polygon_mode == PolygonMode::Line {
+                let (a, b) = (&vs_outputs[i], &vs_outputs[(i + 1) % 3]);
+                push_line(&target, a, b, is_front_facing, depth_bias, triangles);
+            } else {
+                push_point(&target, &vs_outputs[i], is_front_facing, depth_bias, triangles);
+            }
+        }
+        return;
+    }
+
+    for indices in clip::triangle_fan(polygon.len()) {
+        let (a, b, c) = (indices[0], indices[1], indices[2]);
+        let positions = [polygon_ss[a], polygon_ss[b], polygon_ss[c]];
+
+        // Degenerate triangles and the ones which fall between samples are dropped here.
+        let setup = match target.setup(&positions) {
+            Some(setup) => setup,
+            None => continue,
+        };
+        let is_front_facing = is_front_facing(rasterizer_state, setup.is_counter_clockwise());
+        if is_culled(rasterizer_state, is_front_facing) {
+            continue;
+        }
+
+        triangles.push(ScreenTriangle {
+            positions: positions,
+            varyings: [polygon[a].varyings, polygon[b].varyings, polygon[c].varyings],
+            perspective: [
+                polygon_perspective[a],
+                polygon_perspective[b],
+                polygon_perspective[c],
+            ],
+            is_front_facing: is_front_facing,
+            setup: setup,
+        });
+    }
+}
+
+/// Push line made of already shaded vertices to `triangles`, see `push_line`. Lines are
+/// never culled.
+fn process_line<T: Varying>(
+    vs_outputs: &[VSOutput<T>],
+    viewports: &[ScreenViewport],
+    rasterizer_state: &RasterizerState,
+    samples: &[(i32, i32)],
+    triangles: &mut Vec<ScreenTriangle<T>>,
+) {
+    let index = vs_outputs[0].viewport_index;
+    if let Some(target) = PrimitiveTarget::new(viewports, index, rasterizer_state, samples) {
+        let (a, b) = (&vs_outputs[0], &vs_outputs[1]);
+        push_line(&target, a, b, true, rasterizer_state.depth_bias, triangles);
+    }
+}
+
+/// Push already shaded point to `triangles`, see `push_point`.
+fn process_point<T: Varying>(
+    vs_output: &VSOutput<T>,
+    viewports: &[ScreenViewport],
+    rasterizer_state: &RasterizerState,
+    samples: &[(i32, i32)],
+    triangles: &mut Vec<ScreenTriangle<T>>,
+) {
+    let index = vs_output.viewport_index;
+    if let Some(target) = PrimitiveTarget::new(viewports, index, rasterizer_state, samples) {
+        push_point(&target, vs_output, true, rasterizer_state.depth_bias, triangles);
+    }
+}
+
+/// Clip line from `a` to `b` and push the two triangles making up its
+/// `RasterizerState::line_width` wide quad to `triangles`, with `depth_bias` added to depth.
+fn push_line<T: Varying>(
+    target: &PrimitiveTarget,
+    a: &VSOutput<T>,
+    b: &VSOutput<T>,
+    is_front_facing: bool,
+    depth_bias: f32,
+    triangles: &mut Vec<ScreenTriangle<T>>,
+) {
+    let (start, end) = match clip::clip_line(a, b) {
+        Some(line) => line,
+        None => return,
+    };
+
+    let viewport = target.viewport;
+    let start_ss = viewport.with_depth_bias(viewport.to_screen(start.position), depth_bias);
+    let end_ss = viewport.with_depth_bias(viewport.to_screen(end.position), depth_bias);
+    let direction = Vector2::new(end_ss.x - start_ss.x, end_ss.y - start_ss.y);
+    if direction.magnitude2() == 0.0 {
+        return;
+    }
+    let side = Vector2::new(-direction.y, direction.x).normalize() * target.state.line_width;
+    let side = side.extend(0.0) / 2.0;
+    let (start_perspective, end_perspective) = (
+        perspective_factor(target.state, &start),
+        perspective_factor(target.state, &end),
+    );
+    push_quad(
+        target,
+        [start_ss - side, end_ss - side, end_ss + side, start_ss + side],
+        [start.varyings, end.varyings, end.varyings, start.varyings],
+        [start_perspective, end_perspective, end_perspective, start_perspective],
+        is_front_facing,
+        triangles,
+    );
+}
+
+/// Push the two triangles making up `RasterizerState::point_size` wide square centered on
+/// `vertex` to `triangles`, if the vertex is inside of the frustum.
+fn push_point<T: Varying>(
+    target: &PrimitiveTarget,
+    vertex: &VSOutput<T>,
+    is_front_facing: bool,
+    depth_bias: f32,
+    triangles: &mut Vec<ScreenTriangle<T>>,
+) {
+    if !clip::point_inside(vertex) {
+        return;
+    }
+
+    let center = target.viewport.to_screen(vertex.position);
+    let center = target.viewport.with_depth_bias(center, depth_bias);
+    let half = target.state.point_size / 2.0;
+    let (dx, dy) = (Vector3::new(half, 0.0, 0.0), Vector3::new(0.0, half, 0.0));
+    let perspective = perspective_factor(target.state, vertex);
+    push_quad(
+        target,
+        [center - dx - dy, center + dx - dy, center + dx + dy, center - dx + dy],
+        [vertex.varyings; 4],
+        [perspective; 4],
+        is_front_facing,
+        triangles,
+    );
+}
+
+/// Push quad with corners in `positions` as two triangles sharing the diagonal from the first
+/// to the third corner.
+fn push_quad<T: Varying>(
+    target: &PrimitiveTarget,
+    positions: [Vector3<f32>; 4],
+    varyings: [T; 4],
+    perspective: [f32; 4],
+    is_front_facing: bool,
+    triangles: &mut Vec<ScreenTriangle<T>>,
+) {
+    for &(a, b, c) in &[(0, 1, 2), (0, 2, 3)] {
+        let positions = [positions[a], positions[b], positions[c]];
+        let setup = match target.setup(&positions) {
+            Some(setup) => setup,
+            None => continue,
+        };
+        triangles.push(ScreenTriangle {
+            positions: positions,
+            varyings: [varyings[a], varyings[b], varyings[c]],
+            perspective: [perspective[a], perspective[b], perspective[c]],
+            is_front_facing: is_front_facing,
+            setup: setup,
+        });
+    }
+}
+
+/// True if triangle with the given screen winding is front facing, as set by `FrontFace`.
+#[inline]
+fn is_front_facing(rasterizer_state: &RasterizerState, counter_clockwise: bool) -> bool {
+    match rasterizer_state.front_face {
+        FrontFace::CounterClockwise => counter_clockwise,
+        FrontFace::Clockwise => !counter_clockwise,
+    }
+}
+
+#[inline]
+fn is_culled(rasterizer_state: &RasterizerState, is_front_facing: bool) -> bool {
+    match rasterizer_state.cull_mode {
+        CullMode::None => false,
+        CullMode::Front => is_front_facing,
+        CullMode::Back => !is_front_facing,
+    }
+}
+
+/// Largest change of depth per pixel along x or y across the plane of screen space `polygon`.
+fn depth_slope(polygon: &[Vector3<f32>]) -> f32 {
+    for indices in clip::triangle_fan(polygon.len()) {
+        let e1 = polygon[indices[1]] - polygon[indices[0]];
+        let e2 = polygon[indices[2]] - polygon[indices[0]];
+        let det = e1.x * e2.y - e2.x * e1.y;
+        if det == 0.0 {
+            continue;
+        }
+        let dz_dx = (e1.z * e2.y - e2.z * e1.y) / det;
+        let dz_dy = (e2.z * e1.x - e1.z * e2.x) / det;
+        return dz_dx.abs().max(dz_dy.abs());
+    }
+    0.0
+}
+
+/// Factor `vs_output` attributes are weighted with when interpolating them, see
+/// `ScreenTriangle::perspective`.
+#[inline]
+fn perspective_factor<T>(rasterizer_state: &RasterizerState, vs_output: &VSOutput<T>) -> f32 {
+    match rasterizer_state.interpolation {
+        Interpolation::Perspective => 1.0 / vs_output.position.w,
+        Interpolation::NoPerspective => 1.0,
+    }
+}
+
+
+/// Split `count` items to at most `workers` continuous `(start, end)` ranges of similar size.
+fn work_ranges(count: usize, workers: usize) -> Vec<(usize, usize)> {
+    let chunk_size = cmp::max(1, (count + workers - 1) / workers);
+    (0..(count + chunk_size - 1) / chunk_size)
+        .map(|c| (c * chunk_size, cmp::min((c + 1) * chunk_size, count)))
+        .collect()
+}
+
+
+/// State used when writing shaded pixels to the tiles, copied from `Gl` at the start of a draw.
+struct OutputState {
+    depth: DepthState,
+    stencil: StencilState,
+    blend: blend::BlendState,
+}
+
+
+/// Rectangular part of the render targets together with its depth and stencil values.
+/// Every tile is rasterized by one worker at a time, so it can be written without any locking.
+///
+/// Render target values, depth and stencil are stored for every sample, with samples of one
+/// pixel next to each other.
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    samples: &'static [(i32, i32)],
+    targets: Vec<target::Buffer>,
     zb: Vec<f32>,
+    /// Whether depth of the sample was written since the last clear.
+    depth_written: Vec<bool>,
+    sb: Vec<u8>,
 }
 
-impl Gl {
-    pub fn new(viewport_width: u32, viewport_height: u32) -> Gl {
-        let framebuffer: Vec<u32> = vec![0; (viewport_width * viewport_height) as usize];
-        let framebuffer_width = viewport_width as usize;
-        let zbuffer: Vec<f32> = vec![-99999999.0; (viewport_width * viewport_height) as usize];
-        Gl {
-            viewport_dimensions: (viewport_width, viewport_height),
-            fb: framebuffer,
-            fb_width: framebuffer_width,
-            zb: zbuffer,
+impl Tile {
+    fn new(x: u32, y: u32, width: u32, height: u32, samples: &'static [(i32, i32)]) -> Tile {
+        let len = (width * height) as usize * samples.len();
+        Tile {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+            samples: samples,
+            targets: vec![target::Buffer::new(target::Format::Rgba8, len)],
+            zb: vec![1.0; len],
+            depth_written: vec![false; len],
+            sb: vec![0; len],
+        }
+    }
+
+    fn len(&self) -> usize {
+        (self.width * self.height) as usize * self.samples.len()
+    }
+
+    fn clear_depth(&mut self, depth: f32) {
+        for z in &mut self.zb {
+            *z = depth;
+        }
+        for written in &mut self.depth_written {
+            *written = false;
+        }
+    }
+
+    fn clear_stencil(&mut self, value: u8) {
+        for v in &mut self.sb {
+            *v = value;
         }
     }
 
-    pub fn draw<V, P>(
+    /// Rasterize part of the `triangle` which overlaps this tile.
+    ///
+    /// Pixels are shaded in 2x2 quads aligned to even coordinates. Varyings are interpolated
+    /// for all four pixels of a quad with any covered pixel, including the uncovered helper
+    /// pixels, so the pixel shader gets their differences as `ddx` and `ddy`. Only covered
+    /// pixels are shaded and written.
+    fn rasterize<U, P, T>(
         &mut self,
-        model: &model::Model,
-        vertex_shader: V,
-        vertex_shader_input: VSInput,
-        pixel_shader: P,
-        pixel_shader_input: PSInput,
+        triangle: &ScreenTriangle<T>,
+        state: &OutputState,
+        uniforms: &U,
+        pixel_shader: &P,
     ) where
-        V: Fn(VSInput) -> VSOutput + Send + Copy + 'static,
-        P: Fn(PSInput) -> Vector4<f32> + Send + Copy + 'static,
+        P: PixelShader<U, T>,
+        T: Varying,
     {
+        let (bb_min_x, bb_min_y, bb_max_x, bb_max_y) = triangle.setup.bounding_box;
+        let min_x = cmp::max(bb_min_x, self.x);
+        let min_y = cmp::max(bb_min_y, self.y);
+        let max_x = cmp::min(bb_max_x, self.x + self.width - 1);
+        let max_y = cmp::min(bb_max_y, self.y + self.height - 1);
+
+        let mut masks = [0u32; 4];
+        let mut sample_depths = [[0.0f32; 8]; 4];
+        for qy in (min_y / 2)..(max_y / 2 + 1) {
+            for qx in (min_x / 2)..(max_x / 2 + 1) {
+                let (x0, y0) = (qx * 2, qy * 2);
+                for q in 0..4usize {
+                    let (x, y) = (x0 + q as u32 % 2, y0 + q as u32 / 2);
+                    masks[q] = if x < min_x || x > max_x || y < min_y || y > max_y {
+                        0
+                    } else {
+                        self.test_pixel(triangle, state, x, y, &mut sample_depths[q])
+                    };
+                }
+                if masks.iter().all(|&m| m == 0) {
+                    continue;
+                }
 
-        let viewport: Matrix4<f32> = viewport_matrix(self.viewport_dimensions, CLIP_NEAR, CLIP_FAR);
-
-        let (tx, rx) = sync::mpsc::channel();
-        let fb_width = self.fb_width;
-
-        for face in model.faces.clone() {
-            let tx = tx.clone();
-
-            let mut ps_input = pixel_shader_input.clone();
-            let mut vs_input = vertex_shader_input;
-            thread::spawn(move || {
-                let mut result = FaceThreadResult {
-                    bi: Vec::with_capacity(1000),
-                    fbv: Vec::with_capacity(1000),
-                    zbv: Vec::with_capacity(1000),
-                };
-
-                let mut face_ss: Vec<Vector3<f32>> = Vec::with_capacity(3);
-                let mut face_2d: Vec<Vector2<u32>> = Vec::with_capacity(3);
-                let mut texcoords: Vec<Vector2<f32>> = Vec::with_capacity(3);
-                let mut normals: Vec<Vector3<f32>> = Vec::with_capacity(3);
-                for vertex in &face.verts {
-                    vs_input.position = vertex.pos.extend(1.0);
-                    vs_input.normal = vertex.normal.extend(0.0);
-                    vs_input.texcoord = vertex.texcoord;
-                    let vs_out: VSOutput = vertex_shader(vs_input);
-                    let vs_pos = vs_out.position;
-                    let ndc_v = Vector4::<f32>::new(
-                        vs_pos.x / vs_pos.w,
-                        vs_pos.y / vs_pos.w,
-                        vs_pos.z / vs_pos.w,
-                        1.0,
+                // Every pixel is shaded once at its center, even if the center itself isn't
+                // covered.
+                let mut varyings = [triangle.varyings[0]; 4];
+                let mut depths = [0.0f32; 4];
+                for q in 0..4 {
+                    let bary = triangle.setup.barycentric(x0 + q as u32 % 2, y0 + q as u32 / 2);
+                    let attr_bary = perspective_correct(bary, &triangle.perspective);
+                    depths[q] = triangle.depth(bary);
+                    varyings[q] = T::interpolate_screen(
+                        &triangle.varyings[0],
+                        &triangle.varyings[1],
+                        &triangle.varyings[2],
+                        attr_bary,
+                        bary,
                     );
-                    let s_s_v = viewport * ndc_v;
-                    let mut v3: Vector3<f32> = s_s_v.truncate();
-                    v3.x = v3.x.round();
-                    v3.y = v3.y.round();
-                    face_ss.push(v3);
-                    face_2d.push(v3.truncate().cast());
-                    normals.push(vs_out.normal.truncate());
-                    texcoords.push(vs_out.texcoord);
                 }
 
-                let triangle = triangle::TriangleIterator::new(&face_2d);
-                for line in triangle {
-                    for point in line {
-                        let bary = match triangle::barycentric(
-                            Vector2::new(point.0 as f32, point.1 as f32),
-                            &face_ss,
-                        ) {
-                            Some(b) => b,
-                            None => continue,
-                        };
-                        result.bi.push(utils::xy(point.0, point.1, fb_width));
-                        result.zbv.push(
-                            face_ss[0].z * bary.x + face_ss[1].z * bary.y +
-                                face_ss[2].z * bary.z,
-                        );
-
-                        let texcoord = utils::vector2_interpolate(&texcoords, &bary);
-                        let normal = utils::vector3_interpolate(&normals, &bary);
-                        let position = utils::vector3_interpolate(&face_ss, &bary);
-
-                        ps_input.texcoord = texcoord;
-                        ps_input.normal = normal;
-                        ps_input.position = position;
-
-                        // We can't just copy pixel_shader_input as it stores textures as Arc.
-                        let pixel_color = pixel_shader(ps_input.clone());
-                        result.fbv.push(color::v4_as_value(pixel_color));
+                for q in 0..4 {
+                    if masks[q] == 0 {
+                        continue;
                     }
+                    let (row, column) = (q - q % 2, q % 2);
+                    let (x, y) = (x0 + q as u32 % 2, y0 + q as u32 / 2);
+                    let ps_input = PSInput {
+                        position: Vector3::new(x as f32 + 0.5, y as f32 + 0.5, depths[q]),
+                        is_front_facing: triangle.is_front_facing,
+                        varyings: varyings[q],
+                        ddx: T::difference(&varyings[row], &varyings[row + 1]),
+                        ddy: T::difference(&varyings[column], &varyings[column + 2]),
+                    };
+                    let output = pixel_shader.shade(uniforms, &ps_input);
+                    let bi = self.sample_index(x, y);
+                    self.write_pixel(triangle, state, bi, masks[q], &sample_depths[q], &output);
                 }
-                tx.send(result).unwrap();
-            });
+            }
         }
+    }
 
-        for _ in 0..model.faces.len() {
-            let result: FaceThreadResult = rx.recv().unwrap();
-            for i in 0..result.bi.len() {
-                let bi = result.bi[i];
-                let z_b_v = result.zbv[i];
-                let f_b_v = result.fbv[i];
-                if z_b_v >= self.zb[bi] {
-                    self.fb[bi] = f_b_v;
-                    self.zb[bi] = z_b_v;
+    /// Coverage, stencil and depth tests of every sample of pixel `x`, `y`. Bit `s` of the
+    /// returned mask is set if sample `s` passed all of them, with its depth stored in
+    /// `sample_depths`. Stencil values of failed samples are updated right away.
+    fn test_pixel<T>(
+        &mut self,
+        triangle: &ScreenTriangle<T>,
+        state: &OutputState,
+        x: u32,
+        y: u32,
+        sample_depths: &mut [f32; 8],
+    ) -> u32 {
+        let bi = self.sample_index(x, y);
+        let stencil_state = &state.stencil;
+        let stencil_face = stencil_state.face(triangle.is_front_facing);
+        let mut mask = 0u32;
+        for (s, &offset) in self.samples.iter().enumerate() {
+            let sample_bary = match triangle.setup.sample_coverage(x, y, offset) {
+                Some(b) => b,
+                None => continue,
+            };
+            if stencil_state.enable && !stencil_state.test(stencil_face, self.sb[bi + s]) {
+                self.sb[bi + s] = stencil_state.update(stencil_face.fail_op, self.sb[bi + s]);
+                continue;
+            }
+            let z = triangle.depth(sample_bary);
+            if !state.depth.func.compare(z, self.zb[bi + s]) {
+                if stencil_state.enable {
+                    let op = stencil_face.depth_fail_op;
+                    self.sb[bi + s] = stencil_state.update(op, self.sb[bi + s]);
                 }
+                continue;
             }
+            sample_depths[s] = z;
+            mask |= 1 << s;
         }
+        mask
     }
 
-    pub fn save_framebuffer_as_image(&self, path: &path::Path) {
-        let (window_width, window_height) = self.viewport_dimensions;
-        utils::save_buffer_as_image(path, &self.fb, window_width, window_height);
+    /// Write pixel shader `output` to samples set in `mask` of the pixel starting at sample
+    /// index `bi`, together with their depth and stencil.
+    fn write_pixel<T, O: PixelOutput>(
+        &mut self,
+        triangle: &ScreenTriangle<T>,
+        state: &OutputState,
+        bi: usize,
+        mut mask: u32,
+        sample_depths: &[f32; 8],
+        output: &O,
+    ) {
+        let sample_count = self.samples.len();
+        let (depth_state, blend_state) = (&state.depth, &state.blend);
+        let stencil_state = &state.stencil;
+        let stencil_face = stencil_state.face(triangle.is_front_facing);
+        if blend_state.alpha_to_coverage {
+            mask &= alpha_coverage_mask(output.target(0).w, sample_count);
+        }
+        for (t, buffer) in self.targets.iter_mut().enumerate() {
+            if buffer.format() == target::Format::R32Uint {
+                let value = output.target_uint(t);
+                for s in 0..sample_count {
+                    if mask & (1 << s) != 0 {
+                        buffer.set_uint(bi + s, value);
+                    }
+                }
+                continue;
+            }
+            let value = output.target(t);
+            let blend = blend_state.enable && buffer.is_blendable();
+            for s in 0..sample_count {
+                if mask & (1 << s) == 0 {
+                    continue;
+                }
+                if blend {
+                    let dst = buffer.get(bi + s);
+                    buffer.set(bi + s, blend_state.blend(value, dst));
+                } else {
+                    buffer.set(bi + s, value);
+                }
+            }
+        }
+        for s in 0..sample_count {
+            if mask & (1 << s) == 0 {
+                continue;
+            }
+            if depth_state.write_enable {
+                self.zb[bi + s] = sample_depths[s];
+                self.depth_written[bi + s] = true;
+            }
+            if stencil_state.enable {
+                let op = stencil_face.pass_op;
+                self.sb[bi + s] = stencil_state.update(op, self.sb[bi + s]);
+            }
+        }
+    }
+
+    /// Index of the first sample of pixel `x`, `y`.
+    fn sample_index(&self, x: u32, y: u32) -> usize {
+        let i = utils::xy((x - self.x) as usize, (y - self.y) as usize, self.width as usize);
+        i * self.samples.len()
+    }
+}
+
+
+/// Mask of samples covered with given `alpha`, so that the resolved pixel gets its share of
+/// the color when alpha to coverage is enabled.
+fn alpha_coverage_mask(alpha: f32, sample_count: usize) -> u32 {
+    let covered = (utils::saturate(alpha) * sample_count as f32).round() as u32;
+    (1 << covered) - 1
+}
+
+
+pub struct Gl {
+    viewport_dimensions: (u32, u32),
+    tiles: Vec<Tile>,
+    tiles_x: u32,
+    /// Sample positions shared by all tiles.
+    samples: &'static [(i32, i32)],
+    /// Formats of the render targets every tile stores.
+    target_formats: Vec<target::Format>,
+    pool: scoped_threadpool::Pool,
+    viewports: Vec<Viewport>,
+    scissor: Option<Rect>,
+    rasterizer_state: RasterizerState,
+    depth_state: DepthState,
+    stencil_state: StencilState,
+    blend_state: blend::BlendState,
+}
+
+impl Gl {
+    pub fn new(viewport_width: u32, viewport_height: u32) -> Gl {
+        Gl::with_samples(viewport_width, viewport_height, 1)
+    }
+
+    /// Create `Gl` with multisampled framebuffer, storing `sample_count` color and depth
+    /// samples for every pixel. Supported counts are 1, 2, 4 and 8, others are rounded down to
+    /// the nearest supported one.
+    pub fn with_samples(viewport_width: u32, viewport_height: u32, sample_count: u32) -> Gl {
+        let samples = triangle::sample_pattern(sample_count);
+        let tiles_x = (viewport_width + TILE_SIZE - 1) / TILE_SIZE;
+        let tiles_y = (viewport_height + TILE_SIZE - 1) / TILE_SIZE;
+        let mut tiles: Vec<Tile> = Vec::with_capacity((tiles_x * tiles_y) as usize);
+        for ty in 0..tiles_y {
+            for tx in 0..tiles_x {
+                let x = tx * TILE_SIZE;
+                let y = ty * TILE_SIZE;
+                tiles.push(Tile::new(
+                    x,
+                    y,
+                    cmp::min(TILE_SIZE, viewport_width - x),
+                    cmp::min(TILE_SIZE, viewport_height - y),
+                    samples,
+                ));
+            }
+        }
+        Gl {
+            viewport_dimensions: (viewport_width, viewport_height),
+            tiles: tiles,
+            tiles_x: tiles_x,
+            samples: samples,
+            target_formats: vec![target::Format::Rgba8],
+            pool: scoped_threadpool::Pool::new(num_cpus::get() as u32),
+            viewports: vec![Viewport::new(0, 0, viewport_width, viewport_height)],
+            scissor: None,
+            rasterizer_state: RasterizerState::default(),
+            depth_state: DepthState::default(),
+            stencil_state: StencilState::default(),
+            blend_state: blend::BlendState::default(),
+        }
+    }
+
+    /// Number of samples stored for every pixel.
+    pub fn sample_count(&self) -> u32 {
+        self.samples.len() as u32
+    }
+
+    pub fn viewports(&self) -> &[Viewport] {
+        &self.viewports
+    }
+
+    /// Draw following triangles to a single `viewport`, see `set_viewports`.
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.set_viewports(&[viewport]);
+    }
+
+    /// Set viewports which following triangles select with `VSOutput::viewport_index`.
+    ///
+    /// Viewports can be larger than the framebuffer, but the larger they are the less
+    /// subpixel precision is left, see `triangle::max_subpixel_bits`. Panics if any of them
+    /// reaches so far that there's none.
+    pub fn set_viewports(&mut self, viewports: &[Viewport]) {
+        assert!(!viewports.is_empty(), "at least one viewport has to be set");
+        for viewport in viewports {
+            assert!(
+                triangle::max_subpixel_bits(viewport.extent()) > 0,
+                "viewport {:?} is too large to be rasterized",
+                viewport
+            );
+        }
+        self.viewports = viewports.to_vec();
+    }
+
+    pub fn scissor(&self) -> Option<Rect> {
+        self.scissor
+    }
+
+    /// Discard pixels of following draws which lie outside of `scissor`, or disable the
+    /// scissor test with `None`.
+    pub fn set_scissor(&mut self, scissor: Option<Rect>) {
+        self.scissor = scissor;
+    }
+
+    pub fn rasterizer_state(&self) -> RasterizerState {
+        self.rasterizer_state
+    }
+
+    /// Set rasterizer state used by following draws.
+    pub fn set_rasterizer_state(&mut self, state: RasterizerState) {
+        assert!(
+            state.subpixel_bits >= 1 && state.subpixel_bits <= triangle::MAX_SUBPIXEL_BITS,
+            "subpixel bits: {}, are not in supported range",
+            state.subpixel_bits
+        );
+        self.rasterizer_state = state;
+    }
+
+    pub fn depth_state(&self) -> DepthState {
+        self.depth_state
+    }
+
+    /// Set depth state used by following draws.
+    pub fn set_depth_state(&mut self, state: DepthState) {
+        self.depth_state = state;
+    }
+
+    pub fn stencil_state(&self) -> StencilState {
+        self.stencil_state
+    }
+
+    /// Set stencil state used by following draws.
+    pub fn set_stencil_state(&mut self, state: StencilState) {
+        self.stencil_state = state;
+    }
+
+    pub fn blend_state(&self) -> blend::BlendState {
+        self.blend_state
+    }
+
+    /// Set blend state used by following draws.
+    pub fn set_blend_state(&mut self, state: blend::BlendState) {
+        self.blend_state = state;
+    }
+
+    /// Add render target with given `format` and return its index, which is the one passed
+    /// to `PixelOutput::target`. Render target 0 is always the framebuffer.
+    pub fn add_render_target(&mut self, format: target::Format) -> usize {
+        for tile in &mut self.tiles {
+            let len = tile.len();
+            tile.targets.push(target::Buffer::new(format, len));
+        }
+        self.target_formats.push(format);
+        self.target_formats.len() - 1
+    }
+
+    /// Replace the framebuffer with a cleared one of `format`, `Rgba8` by default. Use
+    /// `Srgba8` or `Rgba32F` to shade and blend in linear space and get sRGB encoded colors
+    /// from `framebuffer`.
+    pub fn set_framebuffer_format(&mut self, format: target::Format) {
+        for tile in &mut self.tiles {
+            let len = tile.len();
+            tile.targets[0] = target::Buffer::new(format, len);
+        }
+        self.target_formats[0] = format;
+    }
+
+    pub fn render_target_count(&self) -> usize {
+        self.target_formats.len()
+    }
+
+    /// Fill every render target with `color`, plain vectors are taken as linear colors.
+    pub fn clear_color<C: Into<color::LinearRgba>>(&mut self, color: C) {
+        let color = color.into().0;
+        for tile in &mut self.tiles {
+            for buffer in &mut tile.targets {
+                buffer.clear(color);
+            }
+        }
+    }
+
+    /// Fill render target `index` with `value`.
+    pub fn clear_render_target(&mut self, index: usize, value: Vector4<f32>) {
+        for tile in &mut self.tiles {
+            tile.targets[index].clear(value);
+        }
+    }
+
+    /// Fill the whole depth buffer with `depth`. Use 1.0 to clear to the far plane for the
+    /// default `Less` depth test.
+    pub fn clear_depth(&mut self, depth: f32) {
+        for tile in &mut self.tiles {
+            tile.clear_depth(depth);
+        }
+    }
+
+    /// Fill the whole stencil buffer with `value`.
+    pub fn clear_stencil(&mut self, value: u8) {
+        for tile in &mut self.tiles {
+            tile.clear_stencil(value);
+        }
+    }
+
+    /// Width of the viewport divided by its height.
+    pub fn aspect_ratio(&self) -> f32 {
+        let (viewport_width, viewport_height) = self.viewport_dimensions;
+        viewport_width as f32 / viewport_height as f32
+    }
+
+    /// Draw single instance of `model` with the default `Instance` attributes, see
+    /// `draw_instanced`.
+    pub fn draw<U, V, P>(
+        &mut self,
+        model: &model::Model,
+        uniforms: &U,
+        vertex_shader: &V,
+        pixel_shader: &P,
+    ) where
+        U: Sync,
+        V: VertexShader<U>,
+        P: PixelShader<U, V::Varyings>,
+    {
+        self.draw_instanced(model, &[Instance::default()], uniforms, vertex_shader, pixel_shader);
+    }
+
+    /// Draw `model` once for every element of `instances` in three stages run on the worker
+    /// pool.
+    ///
+    /// First unique vertices of every instance are split between workers which run vertex
+    /// shader on each of them once. Primitives of the model topology are then assembled from
+    /// the shaded vertices by the index buffer, clipped and transformed to screen space
+    /// triangles. Those are binned to the screen tiles they overlap and finally every tile is
+    /// rasterized by a single worker, going through its triangles in submission order, with
+    /// instances submitted in the order of the slice.
+    ///
+    /// Both shaders get a reference to the same `uniforms`, vertex shader gets also index and
+    /// attributes of the instance in `VSInput`. Vertex shader can output any `Varying` type,
+    /// which then gets clipped, interpolated and delivered to the pixel shader in
+    /// `PSInput::varyings`.
+    ///
+    /// Panics if any of the `model` indices is out of range of its vertices.
+    pub fn draw_instanced<U, V, P>(
+        &mut self,
+        model: &model::Model,
+        instances: &[Instance],
+        uniforms: &U,
+        vertex_shader: &V,
+        pixel_shader: &P,
+    ) where
+        U: Sync,
+        V: VertexShader<U>,
+        P: PixelShader<U, V::Varyings>,
+    {
+        let vertex_count = model.vertices.len();
+        if let Some(index) = model.indices.iter().find(|&&i| i as usize >= vertex_count) {
+            panic!("index {} is out of range of {} model vertices", index, vertex_count);
+        }
+        // Framebuffer with zero width or height has nothing to draw to.
+        if self.tiles.is_empty() {
+            return;
+        }
+
+        let state = &OutputState {
+            depth: self.depth_state,
+            stencil: self.stencil_state,
+            blend: self.blend_state,
+        };
+        let (width, height) = self.viewport_dimensions;
+        let framebuffer_rect = Rect::new(0, 0, width, height);
+        let scissor = self.scissor;
+        let viewports: Vec<ScreenViewport> = self.viewports
+            .iter()
+            .map(|viewport| {
+                let mut rect = viewport.rect().intersect(&framebuffer_rect);
+                if let Some(ref scissor) = scissor {
+                    rect = rect.and_then(|r| r.intersect(scissor));
+                }
+                ScreenViewport {
+                    matrix: viewport_matrix(viewport),
+                    bounds: rect.map(|r| (r.x, r.y, r.x + r.width - 1, r.y + r.height - 1)),
+                    depth_range: (
+                        viewport.depth_near.min(viewport.depth_far),
+                        viewport.depth_near.max(viewport.depth_far),
+                    ),
+                }
+            })
+            .collect();
+        let tiles_x = self.tiles_x;
+        // Positions snapped to fixed point have to stay in range for every viewport.
+        let extent = self.viewports.iter().map(|v| v.extent()).max().unwrap_or(0);
+        let rasterizer_state = &RasterizerState {
+            subpixel_bits: cmp::min(
+                self.rasterizer_state.subpixel_bits,
+                triangle::max_subpixel_bits(extent),
+            ),
+            ..self.rasterizer_state
+        };
+        let samples = self.samples;
+        let pool = &mut self.pool;
+        let tiles = &mut self.tiles;
+
+        let viewports = &viewports;
+
+        let workers = pool.thread_count() as usize;
+
+        // Every unique vertex of every instance is shaded exactly once, primitives then just
+        // pick their vertices from the results by index.
+        let vertex_count = model.vertices.len();
+        let ranges = work_ranges(vertex_count * instances.len(), workers);
+        let mut shaded: Vec<Vec<VSOutput<V::Varyings>>> =
+            ranges.iter().map(|_| Vec::new()).collect();
+        pool.scoped(|scope| {
+            for (&(start, end), vs_outputs) in ranges.iter().zip(shaded.iter_mut()) {
+                scope.execute(move || for i in start..end {
+                    let instance_id = i / vertex_count;
+                    let vertex = &model.vertices[i % vertex_count];
+                    let vs_input = VSInput {
+                        position: vertex.pos.extend(1.0),
+                        normal: vertex.normal.extend(0.0),
+                        texcoord: vertex.texcoord,
+                        instance_id: instance_id,
+                        instance: instances[instance_id],
+                    };
+                    vs_outputs.push(vertex_shader.shade(uniforms, &vs_input));
+                });
+            }
+        });
+        let vs_outputs: Vec<VSOutput<V::Varyings>> = shaded.into_iter().flat_map(|c| c.into_iter()).collect();
+        let vs_outputs = &vs_outputs;
+
+        // Primitives are assembled from the shaded vertices and turned to screen space
+        // triangles, lines and points become quads made of two triangles.
+        let topology = model.topology;
+        let primitive_count = model.primitive_count();
+        let ranges = work_ranges(primitive_count * instances.len(), workers);
+        let mut chunks: Vec<Vec<ScreenTriangle<V::Varyings>>> =
+            ranges.iter().map(|_| Vec::new()).collect();
+        pool.scoped(|scope| {
+            for (&(start, end), triangles) in ranges.iter().zip(chunks.iter_mut()) {
+                scope.execute(move || for i in start..end {
+                    let first = (i / primitive_count) * vertex_count;
+                    let indices = model.primitive(i % primitive_count);
+                    let vertices = [
+                        vs_outputs[first + indices[0] as usize],
+                        vs_outputs[first + indices[1] as usize],
+                        vs_outputs[first + indices[2] as usize],
+                    ];
+                    let (state, vertices) = (rasterizer_state, &vertices);
+                    match topology {
+                        model::Topology::PointList => {
+                            process_point(&vertices[0], viewports, state, samples, triangles)
+                        }
+                        model::Topology::LineList |
+                        model::Topology::LineStrip => {
+                            process_line(vertices, viewports, state, samples, triangles)
+                        }
+                        _ => process_triangle(vertices, viewports, state, samples, triangles),
+                    }
+                });
+            }
+        });
+        let triangles: Vec<ScreenTriangle<V::Varyings>> = chunks.into_iter().flat_map(|c| c.into_iter()).collect();
+
+        let mut bins: Vec<Vec<usize>> = vec![Vec::new(); tiles.len()];
+        for (i, triangle) in triangles.iter().enumerate() {
+            let (min_x, min_y, max_x, max_y) = triangle.setup.bounding_box;
+            for ty in (min_y / TILE_SIZE)..(max_y / TILE_SIZE + 1) {
+                for tx in (min_x / TILE_SIZE)..(max_x / TILE_SIZE + 1) {
+                    bins[(tx + ty * tiles_x) as usize].push(i);
+                }
+            }
+        }
+
+        let triangles = &triangles;
+        pool.scoped(|scope| {
+            for (tile, bin) in tiles.iter_mut().zip(bins.iter()) {
+                if bin.is_empty() {
+                    continue;
+                }
+                scope.execute(move || {
+                    for &i in bin {
+                        tile.rasterize(&triangles[i], state, uniforms, pixel_shader);
+                    }
+                });
+            }
+        });
+    }
+
+    /// Draw `model` filled by `pixel_shader` and then its edges by `wireframe_shader` on top,
+    /// using the current rasterizer state for both. Edges are pulled towards the camera with
+    /// depth bias so they don't fight with the surface they lie on.
+    pub fn draw_with_wireframe<U, V, P, W>(
+        &mut self,
+        model: &model::Model,
+        uniforms: &U,
+        vertex_shader: &V,
+        pixel_shader: &P,
+        wireframe_shader: &W,
+    ) where
+        U: Sync,
+        V: VertexShader<U>,
+        P: PixelShader<U, V::Varyings>,
+        W: PixelShader<U, V::Varyings>,
+    {
+        let rasterizer_state = self.rasterizer_state;
+        self.rasterizer_state.polygon_mode = PolygonMode::Fill;
+        self.draw(model, uniforms, vertex_shader, pixel_shader);
+        self.rasterizer_state = RasterizerState {
+            polygon_mode: PolygonMode::Line,
+            depth_bias: rasterizer_state.depth_bias + WIREFRAME_DEPTH_BIAS,
+            slope_scaled_depth_bias: rasterizer_state.slope_scaled_depth_bias +
+                WIREFRAME_SLOPE_SCALED_DEPTH_BIAS * rasterizer_state.line_width / 2.0,
+            ..rasterizer_state
+        };
+        self.draw(model, uniforms, vertex_shader, wireframe_shader);
+        self.rasterizer_state = rasterizer_state;
+    }
+
+    /// Resolve samples of render target `index` in all tiles to one buffer of the viewport size.
+    pub fn render_target(&self, index: usize) -> target::Buffer {
+        let (viewport_width, viewport_height) = self.viewport_dimensions;
+        let format = self.target_formats[index];
+        let mut buffer = target::Buffer::new(format, (viewport_width * viewport_height) as usize);
+        for tile in &self.tiles {
+            let resolved = tile.targets[index].resolve(tile.samples.len());
+            for row in 0..tile.height {
+                let src = (row * tile.width) as usize;
+                let dst = utils::xy(tile.x as usize, (tile.y + row) as usize, viewport_width as usize);
+                buffer.copy_from(dst, &resolved, src, tile.width as usize);
+            }
+        }
+        buffer
+    }
+
+    /// Colors of the framebuffer, which is render target 0, packed as by `color::v4_as_value`.
+    /// Rows go from the top of the viewport. `Rgba32F` framebuffer is taken as linear and
+    /// sRGB encoded, `Srgba8` is already stored encoded.
+    pub fn framebuffer(&self) -> Vec<u32> {
+        match self.render_target(0) {
+            target::Buffer::Rgba8(fb) |
+            target::Buffer::Srgba8(fb) => fb,
+            target::Buffer::Rgba32F(fb) => fb.iter().map(|&c| color::v4_as_srgb_value(c)).collect(),
+            buffer => {
+                (0..buffer.len())
+                    .map(|i| color::v4_as_value(utils::saturate_v4(buffer.get(i))))
+                    .collect()
+            }
+        }
+    }
+
+    /// Framebuffer converted to an image.
+    pub fn framebuffer_image(&self) -> image::RgbaImage {
+        let (viewport_width, viewport_height) = self.viewport_dimensions;
+        utils::buffer_to_image(&self.framebuffer(), viewport_width, viewport_height)
+    }
+
+    /// Combine samples of every pixel of all tiles to one value with `resolve`, which gets
+    /// the tile and the index of the first sample of the pixel in it.
+    fn resolve_pixels<T, F>(&self, default: T, resolve: F) -> Vec<T>
+    where
+        T: Clone,
+        F: Fn(&Tile, usize) -> T,
+    {
+        let (viewport_width, viewport_height) = self.viewport_dimensions;
+        let mut values: Vec<T> = vec![default; (viewport_width * viewport_height) as usize];
+        for tile in &self.tiles {
+            let sample_count = tile.samples.len();
+            for row in 0..tile.height {
+                for column in 0..tile.width {
+                    let src = utils::xy(column as usize, row as usize, tile.width as usize);
+                    let dst = utils::xy(
+                        (tile.x + column) as usize,
+                        (tile.y + row) as usize,
+                        viewport_width as usize,
+                    );
+                    values[dst] = resolve(tile, src * sample_count);
+                }
+            }
+        }
+        values
+    }
+
+    /// Raw values of the depth buffer, in the depth range they were written with. Multisampled
+    /// pixels are resolved to the sample which passes the current depth test against all the
+    /// others, so the nearest one for both `Less` and reverse `Greater` depth. Functions
+    /// without an order, like `Always`, take the first sample.
+    pub fn depth_buffer(&self) -> Vec<f32> {
+        let func = self.depth_state.func;
+        let sample_count = self.samples.len();
+        self.resolve_pixels(0.0, |tile, first| {
+            let samples = &tile.zb[first..first + sample_count];
+            match func {
+                CompareFunc::Less |
+                CompareFunc::LessEqual |
+                CompareFunc::Greater |
+                CompareFunc::GreaterEqual => {
+                    samples.iter().fold(samples[0], |nearest, &z| if func.compare(z, nearest) {
+                        z
+                    } else {
+                        nearest
+                    })
+                }
+                _ => samples[0],
+            }
+        })
+    }
+
+    /// For every pixel whether any of its samples had depth written since the depth buffer
+    /// was last cleared.
+    fn depth_written(&self) -> Vec<bool> {
+        let sample_count = self.samples.len();
+        self.resolve_pixels(false, |tile, first| {
+            tile.depth_written[first..first + sample_count].iter().any(|&w| w)
+        })
+    }
+
+    /// Values of the stencil buffer, taking the first sample of multisampled pixels.
+    pub fn stencil_buffer(&self) -> Vec<u8> {
+        self.resolve_pixels(0, |tile, first| tile.sb[first])
+    }
+
+    /// Depth buffer converted back to view space distances from the camera, using `projection`
+    /// matrix the scene was drawn with and the depth range of the first viewport.
+    pub fn linear_depth(&self, projection: &Matrix4<f32>) -> Vec<f32> {
+        let inverse = projection
+            .invert()
+            .expect("projection matrix has to be invertible");
+        let (range_near, range_far) = (self.viewports[0].depth_near, self.viewports[0].depth_far);
+        self.depth_buffer()
+            .iter()
+            .map(|&depth| {
+                let ndc_z = (depth - range_near) / (range_far - range_near) * 2.0 - 1.0;
+                let view = inverse * Vector4::new(0.0, 0.0, ndc_z, 1.0);
+                -view.z / view.w
+            })
+            .collect()
+    }
+
+    pub fn save_framebuffer_as_image(&self, path: &path::Path) -> io::Result<()> {
+        let (window_width, window_height) = self.viewport_dimensions;
+        utils::save_buffer_as_image(path, &self.framebuffer(), window_width, window_height)
+    }
+
+    /// Save depth buffer as 16 bit grayscale png, see `normalized_depth`.
+    pub fn save_depth_as_png(&self, path: &path::Path) -> io::Result<()> {
+        let (viewport_width, viewport_height) = self.viewport_dimensions;
+        let mut data: Vec<u8> = Vec::with_capacity((viewport_width * viewport_height * 2) as usize);
+        for value in self.normalized_depth() {
+            // Png stores 16 bit values in big endian order.
+            data.push((value >> 8) as u8);
+            data.push(value as u8);
+        }
+        image::save_buffer(
+            path,
+            &data,
+            viewport_width,
+            viewport_height,
+            image::ColorType::Gray(16),
+        )
+    }
+
+    /// Depth buffer stretched to the full range of 16 bit values between the smallest and the
+    /// largest written depth, so the bunched up depth of perspective projections stays visible.
+    /// Pixels without any depth written since the last clear are left at the maximum value.
+    fn normalized_depth(&self) -> Vec<u16> {
+        let depth = self.depth_buffer();
+        let written = self.depth_written();
+        let (min, max) = depth
+            .iter()
+            .zip(&written)
+            .filter(|&(_, &w)| w)
+            .fold((::std::f32::INFINITY, ::std::f32::NEG_INFINITY), |(min, max), (&z, _)| {
+                (min.min(z), max.max(z))
+            });
+        depth
+            .iter()
+            .zip(&written)
+            .map(|(&z, &w)| if !w {
+                u16::max_value()
+            } else if max > min {
+                ((z - min) / (max - min) * 65535.0).round() as u16
+            } else {
+                0
+            })
+            .collect()
+    }
+
+    /// Save raw depth buffer as grayscale pfm, keeping full float precision.
+    pub fn save_depth_as_pfm(&self, path: &path::Path) -> io::Result<()> {
+        let (viewport_width, viewport_height) = self.viewport_dimensions;
+        let depth = self.depth_buffer();
+        let mut file = io::BufWriter::new(try!(fs::File::create(path)));
+        // Negative scale marks little endian data.
+        try!(write!(file, "Pf\n{} {}\n-1.0\n", viewport_width, viewport_height));
+        // Pfm rows go from the bottom of the image.
+        for row in depth.chunks(viewport_width as usize).rev() {
+            for value in row {
+                let bits = value.to_bits();
+                try!(file.write_all(
+                    &[bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8],
+                ));
+            }
+        }
+        file.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color;
+    use depth_stencil::{StencilFaceState, StencilOp};
+    use varying;
+    use image::Pixel;
+
+    const SIZE: u32 = 16;
+
+    /// Model with a single triangle given directly in clip space.
+    fn triangle_model(positions: [(f32, f32); 3]) -> model::Model {
+        triangle_model_at_depth(positions, 0.0)
+    }
+
+    fn triangle_model_at_depth(positions: [(f32, f32); 3], z: f32) -> model::Model {
+        model::Model::new(vertices(&positions, z), vec![0, 1, 2])
+    }
+
+    fn vertices(positions: &[(f32, f32)], z: f32) -> Vec<model::Vertex> {
+        positions
+            .iter()
+            .map(|&(x, y)| {
+                model::Vertex {
+                    pos: Vector3::new(x, y, z),
+                    normal: Vector3::new(0.0, 0.0, 1.0),
+                    texcoord: Vector2::new(0.0, 0.0),
+                }
+            })
+            .collect()
+    }
+
+    /// Vertex shader passing positions through unchanged, for any uniforms.
+    fn passthrough_vertex<U>(_: &U, inputs: &VSInput) -> VSOutput<()> {
+        let mut output = VSOutput::default();
+        output.position = inputs.position;
+        output
+    }
+
+    fn facing_pixel(_: &(), inputs: &PSInput<()>) -> Vector4<f32> {
+        if inputs.is_front_facing {
+            Vector4::new(1.0, 0.0, 0.0, 1.0)
+        } else {
+            Vector4::new(0.0, 1.0, 0.0, 1.0)
+        }
+    }
+
+    fn draw_triangle(gl: &mut Gl, positions: [(f32, f32); 3]) -> Vec<u32> {
+        gl.draw(
+            &triangle_model(positions),
+            &(),
+            &passthrough_vertex,
+            &facing_pixel,
+        );
+        gl.framebuffer()
+    }
+
+    const CCW: [(f32, f32); 3] = [(-0.5, -0.5), (0.5, -0.5), (0.0, 0.5)];
+    const CW: [(f32, f32); 3] = [(-0.5, -0.5), (0.0, 0.5), (0.5, -0.5)];
+
+    #[test]
+    fn test_cull_back() {
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            cull_mode: CullMode::Back,
+            ..RasterizerState::default()
+        });
+        assert!(draw_triangle(&mut gl, CW).iter().all(|&v| v == 0));
+        assert!(draw_triangle(&mut gl, CCW).iter().any(|&v| v != 0));
+    }
+
+    #[test]
+    fn test_cull_front_clockwise() {
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            cull_mode: CullMode::Front,
+            front_face: FrontFace::Clockwise,
+            ..RasterizerState::default()
+        });
+        assert!(draw_triangle(&mut gl, CW).iter().all(|&v| v == 0));
+        assert!(draw_triangle(&mut gl, CCW).iter().any(|&v| v != 0));
+    }
+
+    #[test]
+    fn test_custom_varyings() {
+        // Vertex shader passes horizontal position scaled to 0 - 1 down to the pixel shader,
+        // which writes it as red channel.
+        let vertex = |_: &(), inputs: &VSInput| {
+            VSOutput {
+                position: inputs.position,
+                varyings: (inputs.position.x + 1.0) / 2.0,
+                viewport_index: 0,
+            }
+        };
+        let pixel = |_: &(), inputs: &PSInput<f32>| Vector4::new(inputs.varyings, 0.0, 0.0, 1.0);
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        let model = triangle_model([(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0)]);
+        gl.draw(&model, &(), &vertex, &pixel);
+
+        let fb = gl.framebuffer();
+        // Last pixel of the bottom row lies on the diagonal edge and is left out by fill rule.
+        let row = (SIZE - 1) as usize;
+        for x in 0..(SIZE as usize - 1) {
+            let red = ((fb[utils::xy(x, row, SIZE as usize)] >> 16) & 0xff) as f32 / 255.0;
+            let expected = (x as f32 + 0.5) / SIZE as f32;
+            assert!((red - expected).abs() < 1.0 / 255.0, "pixel {}: {}", x, red);
+        }
+    }
+
+    /// Pixel shader with its own state, multiplying color from uniforms by its weight.
+    struct WeightedPixel {
+        weight: f32,
+    }
+
+    impl PixelShader<Vector4<f32>, ()> for WeightedPixel {
+        type Output = Vector4<f32>;
+
+        fn shade(&self, uniforms: &Vector4<f32>, _: &PSInput<()>) -> Vector4<f32> {
+            *uniforms * self.weight
+        }
+    }
+
+    #[test]
+    fn test_shader_state() {
+        let uniforms = Vector4::new(1.0, 1.0, 1.0, 1.0);
+        let pixel = WeightedPixel { weight: 0.2 };
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.draw(&triangle_model(CCW), &uniforms, &passthrough_vertex, &pixel);
+        let expected = color::v4_as_value(uniforms * 0.2);
+        let fb = gl.framebuffer();
+        assert!(fb.iter().any(|&v| v == expected));
+        assert!(fb.iter().all(|&v| v == expected || v == 0));
+    }
+
+    #[test]
+    fn test_derivatives() {
+        // Varyings change linearly across the screen, so every pixel gets the same derivatives,
+        // including the ones along the diagonal edge whose quads have uncovered helper pixels.
+        let vertex = |_: &(), inputs: &VSInput| {
+            VSOutput {
+                position: inputs.position,
+                varyings: Vector2::new((inputs.position.x + 1.0) / 2.0, inputs.position.y),
+                viewport_index: 0,
+            }
+        };
+        let pixel = |_: &(), inputs: &PSInput<Vector2<f32>>| {
+            let ddx = inputs.ddx * SIZE as f32;
+            let ddy = inputs.ddy * SIZE as f32 / 2.0;
+            Vector4::new(ddx.x, ddy.y.abs(), ddx.y.abs() + ddy.x.abs(), 1.0)
+        };
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        let model = triangle_model([(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0)]);
+        gl.draw(&model, &(), &vertex, &pixel);
+
+        let fb = gl.framebuffer();
+        let written: Vec<u32> = fb.iter().cloned().filter(|&v| v != 0).collect();
+        assert!(written.len() > (SIZE * SIZE / 4) as usize);
+        assert!(written.len() < (SIZE * SIZE) as usize);
+        assert!(written.iter().all(|&v| v == 0xffffff00));
+    }
+
+    #[test]
+    fn test_perspective_correct() {
+        // Halfway between vertices on the screen, where the second one is three times as far.
+        let bary = Vector3::new(0.5, 0.5, 0.0);
+        let corrected = perspective_correct(bary, &[1.0, 1.0 / 3.0, 1.0]);
+        assert!((corrected.x - 0.75).abs() < 1e-6);
+        assert!((corrected.y - 0.25).abs() < 1e-6);
+
+        let linear = perspective_correct(bary, &[1.0, 1.0, 1.0]);
+        assert!((linear.x - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_no_perspective_varying() {
+        // Second vertex is four times as far as the others, with varyings going from 0 at the
+        // others to 1 at it. Screen space value follows the pixel's x, also after the triangle
+        // is clipped to the viewport.
+        let vertex = |_: &(), inputs: &VSInput| {
+            let t = (inputs.position.x + 1.0) / 4.0;
+            let w = 1.0 + 3.0 * t;
+            VSOutput {
+                position: Vector4::new(inputs.position.x * w, inputs.position.y * w, 0.0, w),
+                varyings: (t, varying::NoPerspective(t)),
+                viewport_index: 0,
+            }
+        };
+        let pixel = |_: &(), inputs: &PSInput<(f32, varying::NoPerspective<f32>)>| {
+            Vector4::new(inputs.varyings.0, (inputs.varyings.1).0, 0.0, 1.0)
+        };
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_framebuffer_format(target::Format::Rgba32F);
+        let model = triangle_model([(-1.0, -1.0), (3.0, -1.0), (-1.0, 3.0)]);
+        gl.draw(&model, &(), &vertex, &pixel);
+        let fb = gl.render_target(0);
+        let value = fb.get(utils::xy(8, 8, SIZE as usize));
+        assert!((value.y - 8.5 / 32.0).abs() < 1e-4, "{:?}", value);
+        assert!(value.x < value.y - 0.1, "{:?}", value);
+    }
+
+    #[test]
+    fn test_is_front_facing() {
+        let red = color::v4_as_value(Vector4::new(1.0, 0.0, 0.0, 1.0));
+        let green = color::v4_as_value(Vector4::new(0.0, 1.0, 0.0, 1.0));
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        let fb = draw_triangle(&mut gl, CCW);
+        assert!(fb.iter().any(|&v| v == red));
+        assert!(fb.iter().all(|&v| v != green));
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        let fb = draw_triangle(&mut gl, CW);
+        assert!(fb.iter().any(|&v| v == green));
+        assert!(fb.iter().all(|&v| v != red));
+    }
+
+    /// Draw full screen triangle at ndc depth `z` with color given in uniforms.
+    fn draw_layer(gl: &mut Gl, z: f32, color: Vector4<f32>) {
+        let model = triangle_model_at_depth([(-1.0, -1.0), (3.0, -1.0), (-1.0, 3.0)], z);
+        draw_model(gl, &model, color);
+    }
+
+    fn draw_layer_model(gl: &mut Gl, positions: [(f32, f32); 3], color: Vector4<f32>) {
+        draw_model(gl, &triangle_model(positions), color);
+    }
+
+    fn draw_model(gl: &mut Gl, model: &model::Model, color: Vector4<f32>) {
+        let pixel = |color: &Vector4<f32>, _: &PSInput<()>| *color;
+        gl.draw(model, &color, &passthrough_vertex, &pixel);
+    }
+
+    #[test]
+    fn test_depth_func() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let green = Vector4::new(0.0, 1.0, 0.0, 1.0);
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        draw_layer(&mut gl, 0.0, red);
+        draw_layer(&mut gl, 0.5, green);
+        assert!(gl.framebuffer().iter().all(|&v| v == color::v4_as_value(red)));
+
+        gl.set_depth_state(DepthState {
+            func: CompareFunc::Greater,
+            ..DepthState::default()
+        });
+        draw_layer(&mut gl, 0.5, green);
+        assert!(gl.framebuffer().iter().all(|&v| v == color::v4_as_value(green)));
+
+        gl.set_depth_state(DepthState {
+            func: CompareFunc::Never,
+            ..DepthState::default()
+        });
+        draw_layer(&mut gl, 0.0, red);
+        assert!(gl.framebuffer().iter().all(|&v| v == color::v4_as_value(green)));
+    }
+
+    #[test]
+    fn test_depth_write_disabled() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let green = Vector4::new(0.0, 1.0, 0.0, 1.0);
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_depth_state(DepthState {
+            write_enable: false,
+            ..DepthState::default()
+        });
+        draw_layer(&mut gl, 0.0, red);
+        // Red layer left the cleared depth untouched, so farther green one still passes.
+        draw_layer(&mut gl, 0.5, green);
+        assert!(gl.framebuffer().iter().all(|&v| v == color::v4_as_value(green)));
+    }
+
+    #[test]
+    fn test_depth_range() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+
+        // Ndc depth 0 is written as 0.5 with the default range and 0.75 with the one below.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.clear_depth(0.6);
+        draw_layer(&mut gl, 0.0, red);
+        assert!(gl.framebuffer().iter().all(|&v| v == color::v4_as_value(red)));
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.clear_depth(0.6);
+        gl.set_viewport(Viewport {
+            depth_near: 0.5,
+            ..Viewport::new(0, 0, SIZE, SIZE)
+        });
+        draw_layer(&mut gl, 0.0, red);
+        assert!(gl.framebuffer().iter().all(|&v| v == 0));
+
+        // Biased depth is clamped to the range.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            depth_bias: -10.0,
+            ..RasterizerState::default()
+        });
+        draw_layer(&mut gl, 0.0, red);
+        assert!(gl.depth_buffer().iter().all(|&z| z == 0.0));
+    }
+
+    #[test]
+    fn test_clear() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let blue = Vector4::new(0.0, 0.0, 1.0, 1.0);
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        draw_layer(&mut gl, 0.0, red);
+
+        // Next frame draws farther layer, which is visible only after the depth is cleared.
+        gl.clear_color(blue);
+        assert!(gl.framebuffer().iter().all(|&v| v == color::v4_as_value(blue)));
+        draw_layer(&mut gl, 0.5, red);
+        assert!(gl.framebuffer().iter().all(|&v| v == color::v4_as_value(blue)));
+        gl.clear_depth(1.0);
+        draw_layer(&mut gl, 0.5, red);
+        assert!(gl.framebuffer().iter().all(|&v| v == color::v4_as_value(red)));
+    }
+
+    #[test]
+    fn test_blend_in_submission_order() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 0.5);
+        let blue = Vector4::new(0.0, 0.0, 1.0, 0.5);
+
+        // Viewport spans several tiles, every one of them has to blend layers in draw order.
+        let size = TILE_SIZE * 2 + 3;
+        let mut gl = Gl::new(size, size);
+        gl.clear_color(Vector4::new(0.0, 0.0, 0.0, 1.0));
+        gl.set_depth_state(DepthState {
+            func: CompareFunc::LessEqual,
+            ..DepthState::default()
+        });
+        gl.set_blend_state(blend::BlendState::alpha());
+        draw_layer(&mut gl, 0.0, red);
+        draw_layer(&mut gl, 0.0, blue);
+
+        let expected = color::v4_as_value(Vector4::new(0.25, 0.0, 0.5, 1.0));
+        assert!(gl.framebuffer().iter().all(|&v| v == expected));
+
+        // Depth test still applies to blended draws.
+        draw_layer(&mut gl, 0.5, red);
+        assert!(gl.framebuffer().iter().all(|&v| v == expected));
+    }
+
+    fn red(v: u32) -> u32 {
+        (v >> 16) & 0xff
+    }
+
+    #[test]
+    fn test_msaa_edge() {
+        let white = Vector4::new(1.0, 1.0, 1.0, 1.0);
+        let model = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0)];
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        draw_layer_model(&mut gl, model, white);
+        assert!(gl.framebuffer().iter().all(|&v| red(v) == 0 || red(v) == 255));
+
+        // Pixels on the diagonal edge are partially covered and get blended with the background.
+        let mut gl = Gl::with_samples(SIZE, SIZE, 4);
+        draw_layer_model(&mut gl, model, white);
+        let fb = gl.framebuffer();
+        assert!(fb.iter().any(|&v| red(v) > 0 && red(v) < 255));
+        // Pixels away from the edge are not affected.
+        assert_eq!(red(fb[utils::xy(0, (SIZE - 1) as usize, SIZE as usize)]), 255);
+        assert_eq!(red(fb[utils::xy((SIZE - 1) as usize, 0, SIZE as usize)]), 0);
+    }
+
+    #[test]
+    fn test_msaa_shades_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let invocations = AtomicUsize::new(0);
+        let pixel = |count: &AtomicUsize, _: &PSInput<()>| {
+            count.fetch_add(1, Ordering::SeqCst);
+            Vector4::new(1.0, 1.0, 1.0, 1.0)
+        };
+        let model = triangle_model([(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0)]);
+
+        // Every pixel with at least one covered sample is shaded exactly once.
+        let mut gl = Gl::with_samples(SIZE, SIZE, 8);
+        gl.draw(&model, &invocations, &passthrough_vertex, &pixel);
+        let covered = gl.framebuffer().iter().filter(|&&v| v != 0).count();
+        assert!(covered > (SIZE * SIZE / 2) as usize);
+        assert_eq!(invocations.load(Ordering::SeqCst), covered);
+    }
+
+    #[test]
+    fn test_alpha_to_coverage() {
+        let mut gl = Gl::with_samples(SIZE, SIZE, 4);
+        gl.set_blend_state(blend::BlendState {
+            alpha_to_coverage: true,
+            ..blend::BlendState::default()
+        });
+        draw_layer(&mut gl, 0.0, Vector4::new(1.0, 0.0, 0.0, 0.5));
+        // Half of the samples are written, the other half keeps the cleared black color.
+        let fb = gl.framebuffer();
+        assert!(fb.iter().all(|&v| red(v) == 128));
+
+        // Depth is written only for the covered samples, so farther layer fills the rest.
+        gl.set_blend_state(blend::BlendState::default());
+        draw_layer(&mut gl, 0.5, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert!(gl.framebuffer().iter().all(|&v| red(v) == 255));
+    }
+
+    /// Outputs of a pixel shader writing three render targets at once.
+    struct GBuffer {
+        albedo: Vector4<f32>,
+        normal: Vector4<f32>,
+        id: u32,
+    }
+
+    impl PixelOutput for GBuffer {
+        fn target(&self, index: usize) -> Vector4<f32> {
+            match index {
+                0 => self.albedo,
+                1 => self.normal,
+                _ => Vector4::new(self.id as f32, 0.0, 0.0, 0.0),
+            }
+        }
+
+        fn target_uint(&self, _: usize) -> u32 {
+            self.id
+        }
+    }
+
+    #[test]
+    fn test_multiple_render_targets() {
+        let vertex = |_: &u32, inputs: &VSInput| {
+            VSOutput {
+                position: inputs.position,
+                varyings: inputs.normal,
+                viewport_index: 0,
+            }
+        };
+        let pixel = |id: &u32, inputs: &PSInput<Vector4<f32>>| {
+            GBuffer {
+                albedo: Vector4::new(1.0, 0.0, 0.0, 1.0),
+                normal: inputs.varyings,
+                id: *id,
+            }
+        };
+
+        let mut gl = Gl::with_samples(SIZE, SIZE, 4);
+        assert_eq!(gl.add_render_target(target::Format::Rgba32F), 1);
+        assert_eq!(gl.add_render_target(target::Format::R32Uint), 2);
+        // Id which can't be represented by f32.
+        let id = (1 << 24) + 1;
+        gl.draw(&triangle_model(CCW), &id, &vertex, &pixel);
+
+        let albedo = gl.render_target(0);
+        let normal = gl.render_target(1);
+        let ids = gl.render_target(2);
+        assert_eq!(albedo.len(), (SIZE * SIZE) as usize);
+        let center = utils::xy((SIZE / 2) as usize, (SIZE / 2) as usize, SIZE as usize);
+        assert_eq!(albedo.get(center), Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(normal.get(center), Vector4::new(0.0, 0.0, 1.0, 0.0));
+        // Ids are never averaged, not even on the edges.
+        if let target::Buffer::R32Uint(ref v) = ids {
+            assert_eq!(v[center], id);
+            assert_eq!(v[0], 0);
+            assert!(v.iter().all(|&v| v == 0 || v == id));
+        } else {
+            panic!("id target isn't R32Uint");
+        }
+    }
+
+    #[test]
+    fn test_framebuffer_image() {
+        let mut gl = Gl::new(SIZE, SIZE + 2);
+        gl.clear_color(Vector4::new(0.0, 0.0, 1.0, 1.0));
+        draw_layer_model(&mut gl, CCW, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        let image = gl.framebuffer_image();
+        assert_eq!(image.dimensions(), (SIZE, SIZE + 2));
+        let pixel = |r, g, b, a| image::Rgba::from_channels(r, g, b, a);
+        assert_eq!(*image.get_pixel(0, 0), pixel(0, 0, 255, 255));
+        assert_eq!(*image.get_pixel(SIZE / 2, (SIZE + 2) / 2), pixel(255, 0, 0, 255));
+    }
+
+    #[test]
+    fn test_depth_readback() {
+        let mut gl = Gl::with_samples(SIZE, SIZE, 2);
+        draw_layer(&mut gl, 0.0, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert!(gl.depth_buffer().iter().all(|&z| z == 0.5));
+
+        // Pixels on the edges get the depth of any of their covered samples.
+        let mut msaa = Gl::with_samples(SIZE, SIZE, 4);
+        draw_layer_model(&mut msaa, CCW, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        let covered = msaa.framebuffer().iter().filter(|&&v| v != 0).count();
+        assert_eq!(msaa.depth_buffer().iter().filter(|&&z| z == 0.5).count(), covered);
+
+        // With reverse depth the nearest sample is the largest one.
+        let mut reverse = Gl::with_samples(SIZE, SIZE, 4);
+        reverse.clear_depth(0.0);
+        reverse.set_depth_state(DepthState {
+            func: CompareFunc::Greater,
+            ..DepthState::default()
+        });
+        draw_layer_model(&mut reverse, CCW, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(reverse.depth_buffer().iter().filter(|&&z| z == 0.5).count(), covered);
+
+        // Layer drawn two units in front of the camera is two units far in linear depth.
+        let projection = projection_matrix((90.0f32).to_radians(), 1.0, 1.0, 3.0);
+        let vertex = |projection: &Matrix4<f32>, inputs: &VSInput| {
+            VSOutput {
+                position: *projection * Vector4::new(inputs.position.x, inputs.position.y, -2.0, 1.0),
+                varyings: (),
+                viewport_index: 0,
+            }
+        };
+        let pixel = |_: &Matrix4<f32>, _: &PSInput<()>| Vector4::new(1.0, 1.0, 1.0, 1.0);
+        gl.clear_depth(1.0);
+        let model = triangle_model([(-4.0, -4.0), (12.0, -4.0), (-4.0, 12.0)]);
+        gl.draw(&model, &projection, &vertex, &pixel);
+        assert!(gl.linear_depth(&projection).iter().all(|&z| (z - 2.0).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_save_depth_as_pfm() {
+        use std::io::Read;
+
+        let mut gl = Gl::new(3, 2);
+        gl.clear_depth(0.25);
+        let path = ::std::env::temp_dir().join("rusteriser_test_depth.pfm");
+        gl.save_depth_as_pfm(&path).unwrap();
+
+        let mut data: Vec<u8> = Vec::new();
+        fs::File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+        let header = b"Pf\n3 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        assert_eq!(data.len(), header.len() + 3 * 2 * 4);
+        assert_eq!(&data[header.len()..header.len() + 4], &[0, 0, 128, 62]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_normalized_depth() {
+        // Layers close to each other get the full range, while cleared pixels are left out.
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let mut gl = Gl::new(SIZE, SIZE);
+        draw_model(&mut gl, &triangle_model_at_depth(CCW, 0.9), red);
+        let front = [(-0.5, -0.5), (0.0, -0.5), (-0.5, 0.0)];
+        draw_model(&mut gl, &triangle_model_at_depth(front, 0.8), red);
+        let normalized = gl.normalized_depth();
+        let at = |x, y| normalized[utils::xy(x, y, SIZE as usize)];
+        assert_eq!(at(4, 11), 0);
+        assert_eq!(at(10, 11), 65535);
+        assert_eq!(at(0, 0), 65535);
+
+        // Depth equal to the cleared one still counts when it was written.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.clear_depth(0.5);
+        gl.set_depth_state(DepthState {
+            func: CompareFunc::Always,
+            ..DepthState::default()
+        });
+        draw_model(&mut gl, &triangle_model_at_depth(CCW, 0.0), red);
+        draw_model(&mut gl, &triangle_model_at_depth(front, 0.5), red);
+        let normalized = gl.normalized_depth();
+        let at = |x, y| normalized[utils::xy(x, y, SIZE as usize)];
+        assert_eq!(at(4, 11), 65535);
+        assert_eq!(at(10, 11), 0);
+        assert_eq!(at(0, 0), 65535);
+    }
+
+    #[test]
+    fn test_stencil_mask() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let green = Vector4::new(0.0, 1.0, 0.0, 1.0);
+
+        // First draw marks the triangle in the stencil buffer, second one draws only there.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_stencil_state(StencilState::both_faces(
+            StencilFaceState {
+                pass_op: StencilOp::Replace,
+                ..StencilFaceState::default()
+            },
+            3,
+        ));
+        draw_layer_model(&mut gl, CCW, red);
+        let stencil = gl.stencil_buffer();
+        let marked: Vec<bool> = gl.framebuffer().iter().map(|&v| v != 0).collect();
+        assert!(stencil.iter().zip(marked.iter()).all(|(&v, &m)| v == if m { 3 } else { 0 }));
+
+        gl.set_depth_state(DepthState {
+            func: CompareFunc::Always,
+            ..DepthState::default()
+        });
+        gl.set_stencil_state(StencilState::both_faces(
+            StencilFaceState {
+                func: CompareFunc::Equal,
+                ..StencilFaceState::default()
+            },
+            3,
+        ));
+        draw_layer(&mut gl, 0.0, green);
+        let fb = gl.framebuffer();
+        for (&v, &m) in fb.iter().zip(marked.iter()) {
+            assert_eq!(v, if m { color::v4_as_value(green) } else { 0 });
+        }
+    }
+
+    #[test]
+    fn test_stencil_ops() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.clear_stencil(0x0f);
+        gl.set_depth_state(DepthState {
+            func: CompareFunc::Never,
+            ..DepthState::default()
+        });
+        gl.set_stencil_state(StencilState {
+            enable: true,
+            front: StencilFaceState {
+                depth_fail_op: StencilOp::IncrementWrap,
+                ..StencilFaceState::default()
+            },
+            back: StencilFaceState {
+                fail_op: StencilOp::Invert,
+                func: CompareFunc::Never,
+                ..StencilFaceState::default()
+            },
+            write_mask: 0x3f,
+            ..StencilState::default()
+        });
+
+        // Front facing triangle fails depth test, while back facing one fails stencil test.
+        draw_layer_model(&mut gl, CCW, red);
+        let inside = utils::xy((SIZE / 2) as usize, (SIZE / 2) as usize, SIZE as usize);
+        assert_eq!(gl.stencil_buffer()[inside], 0x10);
+        assert_eq!(gl.stencil_buffer()[0], 0x0f);
+        draw_layer_model(&mut gl, CW, red);
+        assert_eq!(gl.stencil_buffer()[inside], 0x2f);
+        assert!(gl.framebuffer().iter().all(|&v| v == 0));
+    }
+
+    /// Check that only pixels inside of `rects` were drawn to.
+    fn assert_only_inside(fb: &[u32], rects: &[Rect]) {
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let inside = rects.iter().any(|r| {
+                    x >= r.x && x < r.x + r.width && y >= r.y && y < r.y + r.height
+                });
+                let v = fb[utils::xy(x as usize, y as usize, SIZE as usize)];
+                assert_eq!(v != 0, inside, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_viewport_offset() {
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_viewport(Viewport::new(4, 2, 8, 6));
+        draw_layer(&mut gl, 0.0, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_only_inside(&gl.framebuffer(), &[Rect::new(4, 2, 8, 6)]);
+
+        // Viewport reaching past the framebuffer is cut by its edges.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_viewport(Viewport::new(SIZE - 4, SIZE - 4, 8, 8));
+        draw_layer(&mut gl, 0.0, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_only_inside(&gl.framebuffer(), &[Rect::new(SIZE - 4, SIZE - 4, 4, 4)]);
+    }
+
+    #[test]
+    fn test_scissor() {
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_viewport(Viewport::new(0, 0, 8, 8));
+        gl.set_scissor(Some(Rect::new(4, 4, 8, 8)));
+        draw_layer(&mut gl, 0.0, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_only_inside(&gl.framebuffer(), &[Rect::new(4, 4, 4, 4)]);
+
+        // Scissor reaching past the end of u32 range is cut to the viewport, pixels outside of
+        // it stay untouched.
+        let max = u32::max_value();
+        let everything = Rect::new(0, 0, max, max);
+        assert_eq!(everything.intersect(&Rect::new(4, 4, 8, 8)), Some(Rect::new(4, 4, 8, 8)));
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_viewport(Viewport::new(2, 2, 12, 12));
+        gl.set_scissor(Some(Rect::new(6, 4, max, max)));
+        draw_layer(&mut gl, 0.0, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_only_inside(&gl.framebuffer(), &[Rect::new(6, 4, 8, 10)]);
+
+        // Scissor starting past the framebuffer leaves everything untouched.
+        gl.clear_color(Vector4::new(0.0, 0.0, 0.0, 0.0));
+        gl.set_scissor(Some(Rect::new(max - 1, 0, max, max)));
+        draw_layer(&mut gl, 0.0, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_only_inside(&gl.framebuffer(), &[]);
+    }
+
+    #[test]
+    fn test_empty_framebuffer() {
+        let mut gl = Gl::with_samples(0, 0, 4);
+        assert_eq!(gl.sample_count(), 4);
+        draw_layer(&mut gl, 0.0, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(gl.add_render_target(target::Format::Rgba32F), 1);
+        assert_eq!(gl.render_target_count(), 2);
+        assert_eq!(gl.render_target(1).format(), target::Format::Rgba32F);
+        assert!(gl.framebuffer().is_empty());
+    }
+
+    #[test]
+    fn test_large_viewport() {
+        // Only the top left corner of the viewport is inside of the framebuffer, where the
+        // triangle covers the pixels below its diagonal.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_viewport(Viewport::new(0, 0, 1 << 28, 1 << 28));
+        let positions = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0)];
+        draw_layer_model(&mut gl, positions, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        let fb = gl.framebuffer();
+        let size = SIZE as usize;
+        for y in 0..size {
+            for x in (0..size).filter(|&x| x != y) {
+                assert_eq!(red(fb[utils::xy(x, y, size)]) == 255, x < y, "pixel {} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_subpixel_bits_large_viewport() {
+        // Full precision doesn't fit for the viewport, so it's lowered for the draw.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            subpixel_bits: triangle::MAX_SUBPIXEL_BITS,
+            ..RasterizerState::default()
+        });
+        gl.set_viewport(Viewport::new(0, 0, 100000, 100000));
+        let positions = [(-1.0, -1.0), (3.0, -1.0), (-1.0, 3.0)];
+        draw_layer_model(&mut gl, positions, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert!(gl.framebuffer().iter().all(|&v| red(v) == 255));
+    }
+
+    #[test]
+    #[should_panic(expected = "not in supported range")]
+    fn test_subpixel_bits_out_of_range() {
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            subpixel_bits: triangle::MAX_SUBPIXEL_BITS + 1,
+            ..RasterizerState::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "too large to be rasterized")]
+    fn test_viewport_too_large() {
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_viewport(Viewport::new(0, 0, 1 << 30, 1 << 30));
+    }
+
+    #[test]
+    fn test_viewport_index() {
+        // Vertex shader sends the triangle to the viewport given in uniforms.
+        let vertex = |index: &usize, inputs: &VSInput| {
+            VSOutput {
+                position: inputs.position,
+                varyings: (),
+                viewport_index: *index,
+            }
+        };
+        let pixel = |_: &usize, _: &PSInput<()>| Vector4::new(1.0, 1.0, 1.0, 1.0);
+        let model = triangle_model([(-1.0, -1.0), (3.0, -1.0), (-1.0, 3.0)]);
+        let left = Viewport::new(0, 0, SIZE / 2, SIZE);
+        let right = Viewport::new(SIZE / 2, 0, SIZE / 2, SIZE / 2);
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_viewports(&[left, right]);
+        gl.draw(&model, &1, &vertex, &pixel);
+        assert_only_inside(&gl.framebuffer(), &[right.rect()]);
+        // Index past the last viewport falls back to the first one.
+        gl.draw(&model, &5, &vertex, &pixel);
+        assert_only_inside(&gl.framebuffer(), &[left.rect(), right.rect()]);
+    }
+
+    #[test]
+    fn test_indexed_quad() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let invocations = AtomicUsize::new(0);
+        let vertex = |count: &AtomicUsize, inputs: &VSInput| {
+            count.fetch_add(1, Ordering::SeqCst);
+            VSOutput {
+                position: inputs.position,
+                varyings: (),
+                viewport_index: 0,
+            }
+        };
+        let pixel = |_: &AtomicUsize, _: &PSInput<()>| Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let vertices = vertices(&[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)], 0.0);
+        let model = model::Model::new(vertices, vec![0, 1, 2, 0, 2, 3]);
+
+        // Vertices shared by both triangles are shaded only once.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.draw(&model, &invocations, &vertex, &pixel);
+        assert_eq!(invocations.load(Ordering::SeqCst), 4);
+        assert!(gl.framebuffer().iter().all(|&v| red(v) == 255));
+    }
+
+    #[test]
+    #[should_panic(expected = "index 3 is out of range of 3 model vertices")]
+    fn test_index_out_of_range() {
+        let mut gl = Gl::new(SIZE, SIZE);
+        let model = model::Model::new(vertices(&CCW, 0.0), vec![0, 1, 3]);
+        draw_model(&mut gl, &model, Vector4::new(1.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_topologies() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+
+        // Horizontal line through the centers of the sixth row of pixels.
+        let mut gl = Gl::new(SIZE, SIZE);
+        let line = vertices(&[(-1.0, 0.3125), (0.0, 0.3125), (1.0, 0.3125)], 0.0);
+        let strip = model::Model::with_topology(line, vec![0, 1, 2], model::Topology::LineStrip);
+        draw_model(&mut gl, &strip, red);
+        assert_only_inside(&gl.framebuffer(), &[Rect::new(0, 5, SIZE, 1)]);
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            point_size: 2.0,
+            ..RasterizerState::default()
+        });
+        let points = vertices(&[(0.0, 0.0), (0.0, 3.0)], 0.0);
+        let points = model::Model::with_topology(points, vec![0, 1], model::Topology::PointList);
+        draw_model(&mut gl, &points, red);
+        assert_only_inside(&gl.framebuffer(), &[Rect::new(7, 7, 2, 2)]);
+
+        // Both triangles of the strip keep the winding of the first one, so none is culled.
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            cull_mode: CullMode::Back,
+            ..RasterizerState::default()
+        });
+        let quad = vertices(&[(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)], 0.0);
+        let topology = model::Topology::TriangleStrip;
+        let strip = model::Model::with_topology(quad, vec![0, 1, 2, 3], topology);
+        draw_model(&mut gl, &strip, red);
+        assert_only_inside(&gl.framebuffer(), &[Rect::new(0, 0, SIZE, SIZE)]);
+    }
+
+    #[test]
+    fn test_polygon_mode() {
+        // Edges of the triangle go through pixel centers.
+        let model = triangle_model([(-0.4375, -0.4375), (0.4375, -0.4375), (-0.4375, 0.4375)]);
+        let color = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let pixel = |gl: &Gl, x: usize, y: usize| gl.framebuffer()[utils::xy(x, y, SIZE as usize)];
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            polygon_mode: PolygonMode::Line,
+            ..RasterizerState::default()
+        });
+        draw_model(&mut gl, &model, color);
+        assert!(red(pixel(&gl, 4, 8)) == 255 && red(pixel(&gl, 8, 11)) == 255);
+        assert_eq!(pixel(&gl, 5, 9), 0);
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_rasterizer_state(RasterizerState {
+            polygon_mode: PolygonMode::Point,
+            ..RasterizerState::default()
+        });
+        draw_model(&mut gl, &model, color);
+        assert_only_inside(
+            &gl.framebuffer(),
+            &[Rect::new(4, 11, 1, 1), Rect::new(11, 11, 1, 1), Rect::new(4, 4, 1, 1)],
+        );
+
+        // Edges lie exactly on the surface, so only depth bias lets them pass the depth test.
+        let fill = |_: &(), _: &PSInput<()>| Vector4::new(0.0, 0.0, 1.0, 1.0);
+        let wire = |_: &(), _: &PSInput<()>| Vector4::new(0.0, 1.0, 0.0, 1.0);
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.draw_with_wireframe(&model, &(), &passthrough_vertex, &fill, &wire);
+        assert_eq!(pixel(&gl, 4, 8), color::v4_as_value(Vector4::new(0.0, 1.0, 0.0, 1.0)));
+        assert_eq!(pixel(&gl, 5, 9), color::v4_as_value(Vector4::new(0.0, 0.0, 1.0, 1.0)));
+        assert_eq!(gl.rasterizer_state().polygon_mode, PolygonMode::Fill);
+
+        // Wide edges of a sloped triangle still stay in front of the surface it covers. Left
+        // edge has constant depth while the surface gets closer to the right of it.
+        let mut sloped = triangle_model([(-0.9, -0.9), (0.9, -0.9), (-0.9, 0.9)]);
+        for (vertex, &z) in sloped.vertices.iter_mut().zip(&[0.9, -0.9, 0.9]) {
+            vertex.pos.z = z;
+        }
+        let size = 64;
+        let mut gl = Gl::new(size, size);
+        gl.set_rasterizer_state(RasterizerState {
+            line_width: 6.0,
+            ..RasterizerState::default()
+        });
+        gl.draw_with_wireframe(&sloped, &(), &passthrough_vertex, &fill, &wire);
+        let fb = gl.framebuffer();
+        for x in 3..6 {
+            let value = fb[utils::xy(x, 32, size as usize)];
+            assert_eq!(value, color::v4_as_value(Vector4::new(0.0, 1.0, 0.0, 1.0)));
+        }
+    }
+
+    #[test]
+    fn test_draw_instanced() {
+        let vertex = |_: &(), inputs: &VSInput| {
+            let id = inputs.instance_id as f32 / 3.0;
+            VSOutput {
+                position: inputs.instance.model * inputs.position,
+                varyings: Vector4::new(id, inputs.instance.tint.y, inputs.instance.custom.z, 1.0),
+                viewport_index: 0,
+            }
+        };
+        let pixel = |_: &(), inputs: &PSInput<Vector4<f32>>| inputs.varyings;
+        let quad = vertices(&[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)], 0.0);
+        let model = model::Model::new(quad, vec![0, 1, 2, 0, 2, 3]);
+
+        // Every instance covers one quarter of the screen.
+        let instances: Vec<Instance> = [(-0.5, 0.5), (0.5, 0.5), (-0.5, -0.5), (0.5, -0.5)]
+            .iter()
+            .map(|&(x, y)| {
+                Instance::new(
+                    Matrix4::from_translation(Vector3::new(x, y, 0.0)) * Matrix4::from_scale(0.5),
+                    Vector4::new(1.0, 1.0, 1.0, 1.0),
+                    Vector4::new(0.0, 0.0, 1.0, 0.0),
+                )
+            })
+            .collect();
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.draw_instanced(&model, &instances, &(), &vertex, &pixel);
+
+        let fb = gl.framebuffer();
+        let half = (SIZE / 2) as usize;
+        for (id, &(x, y)) in [(0, 0), (half, 0), (0, half), (half, half)].iter().enumerate() {
+            for py in y..y + half {
+                for px in x..x + half {
+                    let expected = Vector4::new(id as f32 / 3.0, 1.0, 1.0, 1.0);
+                    assert_eq!(fb[utils::xy(px, py, SIZE as usize)], color::v4_as_value(expected));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_normal_matrix() {
+        // Plane x = y stretched twice along x becomes x = 2y, normals have to follow it.
+        let model = Matrix4::from_nonuniform_scale(2.0, 1.0, 1.0);
+        let normal = normal_matrix(&model) * Vector3::new(1.0, -1.0, 0.0);
+        let expected = Vector3::new(1.0, -2.0, 0.0).normalize();
+        assert!((normal.normalize() - expected).magnitude() < 1e-5);
+
+        // Translation doesn't change normals.
+        let model = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(normal_matrix(&model), Matrix3::identity());
+
+        // Mesh flattened to the xy plane has all of its visible normals along z.
+        let model = Matrix4::from_nonuniform_scale(1.0, 1.0, 0.0);
+        let normal = normal_matrix(&model) * Vector3::new(1.0, 1.0, 1.0);
+        assert_eq!(normal, Vector3::new(0.0, 0.0, 1.0));
+
+        // Mirroring keeps normals facing out of the mirrored surface.
+        let model = Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+        let normal = normal_matrix(&model) * Vector3::new(1.0, 0.0, 0.0);
+        assert_eq!(normal, Vector3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_srgb_framebuffer() {
+        let white = Vector4::new(1.0, 1.0, 1.0, 0.5);
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_blend_state(blend::BlendState::alpha());
+        gl.set_framebuffer_format(target::Format::Srgba8);
+        draw_layer(&mut gl, 0.0, white);
+        // Half of the white is blended in linear space and then sRGB encoded.
+        assert!(gl.framebuffer().iter().all(|&v| red(v) == 188));
+
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_blend_state(blend::BlendState::alpha());
+        gl.set_framebuffer_format(target::Format::Rgba32F);
+        draw_layer(&mut gl, 0.0, white);
+        assert!(gl.framebuffer().iter().all(|&v| red(v) == 188));
+        assert_eq!(gl.render_target(0).get(0).x, 0.5);
+    }
+
+    #[test]
+    fn test_typed_colors() {
+        // sRGB encoded colors are decoded to linear before they are stored.
+        let gray = color::Srgba(Vector4::new(0.5, 0.5, 0.5, 1.0));
+        let mut gl = Gl::new(SIZE, SIZE);
+        gl.set_framebuffer_format(target::Format::Rgba32F);
+        gl.clear_color(gray);
+        assert!((gl.render_target(0).get(0).x - 0.214).abs() < 1e-3);
+
+        gl.clear_color(Vector4::new(0.0, 0.0, 0.0, 1.0));
+        let model = triangle_model([(-1.0, -1.0), (3.0, -1.0), (-1.0, 3.0)]);
+        gl.draw(&model, &(), &passthrough_vertex, &|_: &(), _: &PSInput<()>| gray);
+        assert_eq!(gl.render_target(0).get(0), gray.to_linear().0);
     }
 }
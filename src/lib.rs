@@ -7,13 +7,22 @@ extern crate tobj;
 extern crate cgmath;
 extern crate image;
 extern crate rand;
+extern crate scoped_threadpool;
+extern crate num_cpus;
 
 pub mod line;
 pub mod model;
 pub mod color;
 pub mod utils;
 pub mod triangle;
+pub mod clip;
+pub mod blend;
+pub mod depth_stencil;
+pub mod target;
+pub mod texture;
+pub mod varying;
 pub mod gl;
+pub mod camera;
 pub mod shaders;
 
 #[cfg(test)]
@@ -11,7 +11,7 @@ pub enum ModelError {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     pub pos: Vector3<f32>,
     pub normal: Vector3<f32>,
@@ -19,18 +19,70 @@ pub struct Vertex {
 }
 
 
-#[derive(Debug, Clone)]
-pub struct Face {
-    pub verts: Vec<Vertex>,
+/// How the indices of a model are assembled into primitives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topology {
+    /// Every index is a separate point.
+    PointList,
+    /// Every two indices make a separate line.
+    LineList,
+    /// Every index after the first one makes a line with the previous one.
+    LineStrip,
+    /// Every three indices make a separate triangle.
+    TriangleList,
+    /// Every index after the first two makes a triangle with the two previous ones. Every other
+    /// triangle has its first two vertices swapped, so all of them keep the same winding.
+    TriangleStrip,
+    /// Every index after the first two makes a triangle with the previous one and the first one.
+    TriangleFan,
 }
 
+impl Topology {
+    /// Number of vertices making a single primitive.
+    pub fn vertex_count(&self) -> usize {
+        match *self {
+            Topology::PointList => 1,
+            Topology::LineList | Topology::LineStrip => 2,
+            _ => 3,
+        }
+    }
 
+    /// Number of primitives assembled from `index_count` indices.
+    pub fn primitive_count(&self, index_count: usize) -> usize {
+        match *self {
+            Topology::PointList => index_count,
+            Topology::LineList => index_count / 2,
+            Topology::LineStrip => index_count.saturating_sub(1),
+            Topology::TriangleList => index_count / 3,
+            Topology::TriangleStrip | Topology::TriangleFan => index_count.saturating_sub(2),
+        }
+    }
+}
+
+
+/// Indexed mesh. `indices` point to `vertices` and are assembled into primitives as given by
+/// `topology`.
 #[derive(Debug, Clone)]
 pub struct Model {
-    pub faces: Vec<Face>,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub topology: Topology,
 }
 
 impl Model {
+    /// Model made of a triangle list.
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Model {
+        Model::with_topology(vertices, indices, Topology::TriangleList)
+    }
+
+    pub fn with_topology(vertices: Vec<Vertex>, indices: Vec<u32>, topology: Topology) -> Model {
+        Model {
+            vertices: vertices,
+            indices: indices,
+            topology: topology,
+        }
+    }
+
     pub fn load(path: &path::Path) -> Result<Model, ModelError> {
         let (models, _) = match tobj::load_obj(path) {
             Ok(model_and_mats) => model_and_mats,
@@ -41,41 +93,68 @@ impl Model {
         };
         // TODO: We assume it's just one model for now.
         let mesh: &tobj::Mesh = &models[0].mesh;
-        Ok(Model { faces: try!(Model::create_faces(&mesh)) })
+        Ok(Model {
+            vertices: try!(Model::create_vertices(&mesh)),
+            indices: mesh.indices.clone(),
+            topology: Topology::TriangleList,
+        })
     }
 
-    fn create_faces(mesh: &tobj::Mesh) -> Result<Vec<Face>, ModelError> {
+    /// Number of primitives in the model.
+    pub fn primitive_count(&self) -> usize {
+        self.topology.primitive_count(self.indices.len())
+    }
+
+    /// Indices of vertices making primitive `i`. Only the first `Topology::vertex_count` of
+    /// them are used, the rest is set to the first one.
+    pub fn primitive(&self, i: usize) -> [u32; 3] {
+        let indices = &self.indices;
+        match self.topology {
+            Topology::PointList => [indices[i]; 3],
+            Topology::LineList => [indices[i * 2], indices[i * 2 + 1], indices[i * 2]],
+            Topology::LineStrip => [indices[i], indices[i + 1], indices[i]],
+            Topology::TriangleList => [indices[i * 3], indices[i * 3 + 1], indices[i * 3 + 2]],
+            Topology::TriangleStrip if i % 2 == 1 => [indices[i + 1], indices[i], indices[i + 2]],
+            Topology::TriangleStrip => [indices[i], indices[i + 1], indices[i + 2]],
+            Topology::TriangleFan => [indices[0], indices[i + 1], indices[i + 2]],
+        }
+    }
+
+    /// Vertices of primitive `i`, see `primitive`.
+    pub fn primitive_vertices(&self, i: usize) -> [Vertex; 3] {
+        let indices = self.primitive(i);
+        [
+            self.vertices[indices[0] as usize],
+            self.vertices[indices[1] as usize],
+            self.vertices[indices[2] as usize],
+        ]
+    }
+
+    /// Unique vertices of the mesh, which tobj gives with a single index for all attributes.
+    fn create_vertices(mesh: &tobj::Mesh) -> Result<Vec<Vertex>, ModelError> {
         if mesh.normals.is_empty() {
             return Err(ModelError::NoNormals);
         }
         if mesh.texcoords.is_empty() {
             return Err(ModelError::NoTexCoords);
         }
-        let mut faces: Vec<Face> = Vec::with_capacity(mesh.indices.len() / 3);
-        for i in (0..mesh.indices.len()).filter(|i| i % 3 == 0) {
-
-            let mut face = Face { verts: Vec::with_capacity(3) };
-
-            for m in 0..3 {
-                face.verts.push(Vertex {
-                    pos: Vector3::<f32>::new(
-                        mesh.positions[mesh.indices[i + m] as usize * 3],
-                        mesh.positions[mesh.indices[i + m] as usize * 3 + 1],
-                        mesh.positions[mesh.indices[i + m] as usize * 3 + 2],
-                    ),
-                    normal: Vector3::<f32>::new(
-                        mesh.normals[mesh.indices[i + m] as usize * 3],
-                        mesh.normals[mesh.indices[i + m] as usize * 3 + 1],
-                        mesh.normals[mesh.indices[i + m] as usize * 3 + 2],
-                    ),
-                    texcoord: Vector2::<f32>::new(
-                        mesh.texcoords[mesh.indices[i + m] as usize * 2],
-                        mesh.texcoords[mesh.indices[i + m] as usize * 2 + 1],
-                    ),
-                });
-            }
-            faces.push(face);
+        let vertex_count = mesh.positions.len() / 3;
+        let mut vertices: Vec<Vertex> = Vec::with_capacity(vertex_count);
+        for i in 0..vertex_count {
+            vertices.push(Vertex {
+                pos: Vector3::<f32>::new(
+                    mesh.positions[i * 3],
+                    mesh.positions[i * 3 + 1],
+                    mesh.positions[i * 3 + 2],
+                ),
+                normal: Vector3::<f32>::new(
+                    mesh.normals[i * 3],
+                    mesh.normals[i * 3 + 1],
+                    mesh.normals[i * 3 + 2],
+                ),
+                texcoord: Vector2::<f32>::new(mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]),
+            });
         }
-        Ok(faces)
+        Ok(vertices)
     }
 }
@@ -1,38 +1,138 @@
+use std::sync;
 use gl;
+use texture;
 use utils;
+use varying::Varying;
 use cgmath::*;
 
-pub fn simple_vertex(inputs: gl::VSInput) -> gl::VSOutput {
-    let mut output: gl::VSOutput = gl::VSOutput::default();
-    output.position = inputs.projection * inputs.view * inputs.position;
-    output.texcoord = inputs.texcoord;
-    output.normal = inputs.normal;
+
+/// Varyings passed between the shaders in this module.
+#[derive(Debug, Clone, Copy)]
+pub struct Varyings {
+    pub normal: Vector3<f32>,
+    pub texcoord: Vector2<f32>,
+    /// Color the pixel shader output is multiplied by, taken from `gl::Instance::tint`.
+    pub tint: Vector4<f32>,
+    pub world_position: Vector3<f32>,
+}
+
+impl Default for Varyings {
+    fn default() -> Varyings {
+        Varyings {
+            normal: Vector3::new(1.0, 1.0, 1.0),
+            texcoord: Vector2::new(0.0, 0.0),
+            tint: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            world_position: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Varying for Varyings {
+    fn interpolate(a: &Varyings, b: &Varyings, c: &Varyings, weights: Vector3<f32>) -> Varyings {
+        Varyings {
+            normal: Varying::interpolate(&a.normal, &b.normal, &c.normal, weights),
+            texcoord: Varying::interpolate(&a.texcoord, &b.texcoord, &c.texcoord, weights),
+            tint: Varying::interpolate(&a.tint, &b.tint, &c.tint, weights),
+            world_position: Varying::interpolate(
+                &a.world_position,
+                &b.world_position,
+                &c.world_position,
+                weights,
+            ),
+        }
+    }
+}
+
+/// Values shared by the shaders in this module over a whole draw call.
+#[derive(Clone)]
+pub struct Uniforms {
+    /// Transformation from model space to world space, applied after the one of instance.
+    pub model: Matrix4<f32>,
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+    pub camera: Vector3<f32>,
+    pub camera_target: Vector3<f32>,
+    pub light_pos: Vector3<f32>,
+    /// Direction from the camera target towards the camera.
+    pub cam_dir: Vector3<f32>,
+    /// True for parallel projections, where every pixel is viewed along `cam_dir` instead of
+    /// from the `camera` position.
+    pub orthographic: bool,
+    pub textures: Vec<sync::Arc<texture::Texture>>,
+    /// Sampler used for all of the `textures`.
+    pub sampler: texture::Sampler,
+}
+
+impl Default for Uniforms {
+    fn default() -> Uniforms {
+        Uniforms {
+            model: Matrix4::identity(),
+            view: Matrix4::identity(),
+            projection: Matrix4::identity(),
+            camera: Vector3::new(0.0, 0.0, 0.0),
+            camera_target: Vector3::new(0.0, 0.0, 0.0),
+            light_pos: Vector3::new(0.0, 0.0, 0.0),
+            cam_dir: Vector3::new(0.0, 0.0, 0.0),
+            orthographic: false,
+            textures: Vec::new(),
+            sampler: texture::Sampler::default(),
+        }
+    }
+}
+
+
+pub fn simple_vertex(uniforms: &Uniforms, inputs: &gl::VSInput) -> gl::VSOutput<Varyings> {
+    let mut output: gl::VSOutput<Varyings> = gl::VSOutput::default();
+    let model = uniforms.model * inputs.instance.model;
+    let world = model * inputs.position;
+    let normal_matrix = gl::normal_matrix(&model);
+    output.position = uniforms.projection * uniforms.view * world;
+    output.varyings.world_position = world.truncate() / world.w;
+    output.varyings.texcoord = inputs.texcoord;
+    output.varyings.normal = normal_matrix * inputs.normal.truncate();
+    output.varyings.tint = inputs.instance.tint;
     output
 }
 
-pub fn simple_pixel(inputs: gl::PSInput) -> Vector4<f32> {
-    let normal = inputs.normal;
-    let light_dir = inputs.light_pos;
+pub fn simple_pixel(uniforms: &Uniforms, inputs: &gl::PSInput<Varyings>) -> Vector4<f32> {
+    let normal = inputs.varyings.normal;
+    let light_dir = uniforms.light_pos;
     let n = normal.normalize();
     let l = light_dir.normalize();
     let ndotl = utils::saturate(n.dot(l));
-    vec4(ndotl, ndotl, ndotl, 1.0)
+    vec4(ndotl, ndotl, ndotl, 1.0).mul_element_wise(inputs.varyings.tint)
+}
+
+/// Sample texture `index` at the pixel texcoord, with mip level chosen from its derivatives.
+fn sample_texture(
+    uniforms: &Uniforms,
+    inputs: &gl::PSInput<Varyings>,
+    index: usize,
+) -> Vector4<f32> {
+    uniforms.sampler.sample_grad(
+        &uniforms.textures[index],
+        inputs.varyings.texcoord,
+        inputs.ddx.texcoord,
+        inputs.ddy.texcoord,
+    ).0
 }
 
-pub fn diffuse_pixel(inputs: gl::PSInput) -> Vector4<f32> {
-    let texcoord = inputs.texcoord;
-    utils::sample(&inputs.textures[0], texcoord)
+pub fn diffuse_pixel(uniforms: &Uniforms, inputs: &gl::PSInput<Varyings>) -> Vector4<f32> {
+    sample_texture(uniforms, inputs, 0).mul_element_wise(inputs.varyings.tint)
 }
 
-pub fn spec_pixel(inputs: gl::PSInput) -> Vector4<f32> {
-    let texcoord = inputs.texcoord;
-    let normal = inputs.normal;
-    let light_dir = inputs.light_pos;
-    let cam_dir = inputs.cam_dir;
+pub fn spec_pixel(uniforms: &Uniforms, inputs: &gl::PSInput<Varyings>) -> Vector4<f32> {
+    let normal = inputs.varyings.normal;
+    let light_dir = uniforms.light_pos;
+    let cam_dir = if uniforms.orthographic {
+        uniforms.cam_dir
+    } else {
+        uniforms.camera - inputs.varyings.world_position
+    };
 
-    let diffuse_tex = utils::sample(&inputs.textures[0], texcoord);
-    let normals_tex = utils::sample(&inputs.textures[1], texcoord).truncate();
-    let specular_tex = utils::sample(&inputs.textures[2], texcoord).truncate();
+    let diffuse_tex = sample_texture(uniforms, inputs, 0);
+    let normals_tex = sample_texture(uniforms, inputs, 1).truncate();
+    let specular_tex = sample_texture(uniforms, inputs, 2).truncate();
 
     let nrm: Vector3<f32> = Vector3::new(
         normal.x * normals_tex.x,
@@ -55,5 +155,6 @@ pub fn spec_pixel(inputs: gl::PSInput) -> Vector4<f32> {
     let mut ambient = diffuse_tex * 0.1;
     ambient.z *= 1.5;
 
-    utils::saturate_v4(ambient + (diffuse_tex * ndotl) + spec.extend(0.0))
+    let color = utils::saturate_v4(ambient + (diffuse_tex * ndotl) + spec.extend(0.0));
+    color.mul_element_wise(inputs.varyings.tint)
 }
@@ -0,0 +1,240 @@
+//! Render targets written by pixel shaders.
+//!
+//! Every draw writes all render targets bound to `Gl` at once. Pixel shader returns a type
+//! implementing `PixelOutput`, which gives the value for each of them.
+use cgmath::*;
+use color;
+use utils;
+
+
+/// How values of a render target are stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Four 8 bit channels packed as `color::v4_as_value` does, values are clamped to 0 - 1.
+    Rgba8,
+    /// Like `Rgba8`, but linear color channels are sRGB encoded when written and decoded back
+    /// when read, so blending and resolving happen in linear space.
+    Srgba8,
+    /// Four 32 bit floats.
+    Rgba32F,
+    /// Single 32 bit float taken from the `x` channel.
+    R32F,
+    /// Single unsigned integer taken from `PixelOutput::target_uint`, written without going
+    /// through floats so every `u32` is exact. Useful for object ids, it's never blended or
+    /// averaged when resolving samples.
+    R32Uint,
+}
+
+
+/// Values returned by pixel shader, one for every render target.
+pub trait PixelOutput {
+    /// Value written to render target `index`.
+    fn target(&self, index: usize) -> Vector4<f32>;
+
+    /// Value written to integer render target `index`, by default the rounded `x` channel of
+    /// `target`, which is exact only up to 2^24.
+    fn target_uint(&self, index: usize) -> u32 {
+        self.target(index).x.max(0.0).round() as u32
+    }
+}
+
+/// Single color is written to every render target.
+impl PixelOutput for Vector4<f32> {
+    #[inline]
+    fn target(&self, _: usize) -> Vector4<f32> {
+        *self
+    }
+}
+
+/// Linear color is written to every render target.
+impl PixelOutput for color::LinearRgba {
+    #[inline]
+    fn target(&self, _: usize) -> Vector4<f32> {
+        self.0
+    }
+}
+
+/// sRGB encoded color is decoded and written to every render target.
+impl PixelOutput for color::Srgba {
+    #[inline]
+    fn target(&self, _: usize) -> Vector4<f32> {
+        self.to_linear().0
+    }
+}
+
+
+/// Storage of one render target.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Buffer {
+    Rgba8(Vec<u32>),
+    Srgba8(Vec<u32>),
+    Rgba32F(Vec<Vector4<f32>>),
+    R32F(Vec<f32>),
+    R32Uint(Vec<u32>),
+}
+
+impl Buffer {
+    /// Buffer of `len` zeroed values.
+    pub fn new(format: Format, len: usize) -> Buffer {
+        match format {
+            Format::Rgba8 => Buffer::Rgba8(vec![0; len]),
+            Format::Srgba8 => Buffer::Srgba8(vec![0; len]),
+            Format::Rgba32F => Buffer::Rgba32F(vec![Vector4::new(0.0, 0.0, 0.0, 0.0); len]),
+            Format::R32F => Buffer::R32F(vec![0.0; len]),
+            Format::R32Uint => Buffer::R32Uint(vec![0; len]),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match *self {
+            Buffer::Rgba8(_) => Format::Rgba8,
+            Buffer::Srgba8(_) => Format::Srgba8,
+            Buffer::Rgba32F(_) => Format::Rgba32F,
+            Buffer::R32F(_) => Format::R32F,
+            Buffer::R32Uint(_) => Format::R32Uint,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            Buffer::Rgba8(ref v) | Buffer::Srgba8(ref v) => v.len(),
+            Buffer::Rgba32F(ref v) => v.len(),
+            Buffer::R32F(ref v) => v.len(),
+            Buffer::R32Uint(ref v) => v.len(),
+        }
+    }
+
+    /// Value at index `i`, single channel formats have it in `x` with the rest set to
+    /// `(0, 0, 1)`.
+    #[inline]
+    pub fn get(&self, i: usize) -> Vector4<f32> {
+        match *self {
+            Buffer::Rgba8(ref v) => color::value_as_v4(v[i]),
+            Buffer::Srgba8(ref v) => color::srgb_value_as_v4(v[i]),
+            Buffer::Rgba32F(ref v) => v[i],
+            Buffer::R32F(ref v) => Vector4::new(v[i], 0.0, 0.0, 1.0),
+            Buffer::R32Uint(ref v) => Vector4::new(v[i] as f32, 0.0, 0.0, 1.0),
+        }
+    }
+
+    /// Convert `value` to the buffer format and store it at index `i`.
+    #[inline]
+    pub fn set(&mut self, i: usize, value: Vector4<f32>) {
+        match *self {
+            Buffer::Rgba8(ref mut v) => v[i] = color::v4_as_value(utils::saturate_v4(value)),
+            Buffer::Srgba8(ref mut v) => v[i] = color::v4_as_srgb_value(value),
+            Buffer::Rgba32F(ref mut v) => v[i] = value,
+            Buffer::R32F(ref mut v) => v[i] = value.x,
+            Buffer::R32Uint(ref mut v) => v[i] = value.x.max(0.0).round() as u32,
+        }
+    }
+
+    /// Store integer `value` at index `i`. `R32Uint` buffers get it exactly, the other formats
+    /// convert it to float as `set` would.
+    #[inline]
+    pub fn set_uint(&mut self, i: usize, value: u32) {
+        if let Buffer::R32Uint(ref mut v) = *self {
+            v[i] = value;
+            return;
+        }
+        self.set(i, Vector4::new(value as f32, 0.0, 0.0, 1.0));
+    }
+
+    /// Copy `len` values of `src` starting at `src_start` to this buffer starting at `start`.
+    /// Both buffers have to be of the same format.
+    pub fn copy_from(&mut self, start: usize, src: &Buffer, src_start: usize, len: usize) {
+        let (dst_range, src_range) = (start..start + len, src_start..src_start + len);
+        match (self, src) {
+            (&mut Buffer::Rgba8(ref mut d), &Buffer::Rgba8(ref s)) |
+            (&mut Buffer::Srgba8(ref mut d), &Buffer::Srgba8(ref s)) => {
+                d[dst_range].copy_from_slice(&s[src_range])
+            }
+            (&mut Buffer::Rgba32F(ref mut d), &Buffer::Rgba32F(ref s)) => {
+                d[dst_range].copy_from_slice(&s[src_range])
+            }
+            (&mut Buffer::R32F(ref mut d), &Buffer::R32F(ref s)) => {
+                d[dst_range].copy_from_slice(&s[src_range])
+            }
+            (&mut Buffer::R32Uint(ref mut d), &Buffer::R32Uint(ref s)) => {
+                d[dst_range].copy_from_slice(&s[src_range])
+            }
+            _ => panic!("can't copy between render targets of different formats"),
+        }
+    }
+
+    /// Set every value to `value`.
+    pub fn clear(&mut self, value: Vector4<f32>) {
+        for i in 0..self.len() {
+            self.set(i, value);
+        }
+    }
+
+    /// True if values of this buffer can be blended.
+    pub fn is_blendable(&self) -> bool {
+        self.format() != Format::R32Uint
+    }
+
+    /// Buffer with every group of `sample_count` values reduced to one, by averaging them or
+    /// by taking the first sample for `R32Uint`.
+    pub fn resolve(&self, sample_count: usize) -> Buffer {
+        if sample_count == 1 {
+            return self.clone();
+        }
+        let len = self.len() / sample_count;
+        if let Buffer::R32Uint(ref samples) = *self {
+            return Buffer::R32Uint((0..len).map(|i| samples[i * sample_count]).collect());
+        }
+        let mut resolved = Buffer::new(self.format(), len);
+        for i in 0..len {
+            let first = i * sample_count;
+            let sum = (first..first + sample_count).fold(Vector4::new(0.0, 0.0, 0.0, 0.0), |sum, s| {
+                sum + self.get(s)
+            });
+            resolved.set(i, sum / sample_count as f32);
+        }
+        resolved
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_formats() {
+        let value = Vector4::new(1.5, -0.25, 0.5, 1.0);
+        let mut rgba8 = Buffer::new(Format::Rgba8, 1);
+        rgba8.set(0, value);
+        assert_eq!(rgba8.get(0), Vector4::new(1.0, 0.0, 128.0 / 255.0, 1.0));
+
+        let mut rgba32f = Buffer::new(Format::Rgba32F, 1);
+        rgba32f.set(0, value);
+        assert_eq!(rgba32f.get(0), value);
+
+        // Linear half is stored sRGB encoded and decoded back when read.
+        let mut srgba8 = Buffer::new(Format::Srgba8, 1);
+        srgba8.set(0, Vector4::new(0.5, 0.0, 1.0, 0.5));
+        assert_eq!(srgba8, Buffer::Srgba8(vec![0x80bc00ff]));
+        assert!((srgba8.get(0).x - 0.5).abs() < 1e-2);
+
+        let mut id = Buffer::new(Format::R32Uint, 1);
+        id.set(0, Vector4::new(16777215.0, 0.0, 0.0, 0.0));
+        assert_eq!(id, Buffer::R32Uint(vec![16777215]));
+        id.set_uint(0, u32::max_value());
+        assert_eq!(id, Buffer::R32Uint(vec![u32::max_value()]));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut depth = Buffer::new(Format::R32F, 4);
+        depth.set(0, Vector4::new(1.0, 0.0, 0.0, 0.0));
+        depth.set(1, Vector4::new(3.0, 0.0, 0.0, 0.0));
+        assert_eq!(depth.resolve(2), Buffer::R32F(vec![2.0, 0.0]));
+
+        let mut id = Buffer::new(Format::R32Uint, 4);
+        id.set(0, Vector4::new(7.0, 0.0, 0.0, 0.0));
+        id.set(3, Vector4::new(9.0, 0.0, 0.0, 0.0));
+        assert_eq!(id.resolve(2), Buffer::R32Uint(vec![7, 0]));
+    }
+}
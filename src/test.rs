@@ -7,12 +7,15 @@ use cgmath::*;
 use image;
 
 use model;
+use camera;
 use gl;
 use shaders;
 use color;
 use utils;
 use line;
 use triangle;
+use texture;
+use target;
 
 const WINDOW_WIDTH: u32 = 512;
 const WINDOW_HEIGHT: u32 = 512;
@@ -24,17 +27,18 @@ fn test_lines() {
     let mut fb: Vec<u32> = vec![0; (WINDOW_WIDTH * WINDOW_HEIGHT) as usize];
     let fb_width = WINDOW_WIDTH as usize;
     let color = color::Color::red();
-    for face in &testmodel.faces {
+    for t in 0..testmodel.primitive_count() {
+        let verts = testmodel.primitive_vertices(t);
         for i in 0..3 {
             let (x0, y0) = utils::screen_to_image_space(
-                face.verts[i % 3].pos.x,
-                face.verts[i % 3].pos.y,
+                verts[i % 3].pos.x,
+                verts[i % 3].pos.y,
                 WINDOW_WIDTH,
                 WINDOW_HEIGHT,
             );
             let (x1, y1) = utils::screen_to_image_space(
-                face.verts[(i + 1) % 3].pos.x,
-                face.verts[(i + 1) % 3].pos.y,
+                verts[(i + 1) % 3].pos.x,
+                verts[(i + 1) % 3].pos.y,
                 WINDOW_WIDTH,
                 WINDOW_HEIGHT,
             );
@@ -46,7 +50,7 @@ fn test_lines() {
         &fb,
         WINDOW_WIDTH,
         WINDOW_HEIGHT,
-    );
+    ).unwrap();
 }
 
 #[test]
@@ -56,17 +60,18 @@ fn test_lines_iter() {
     let mut fb: Vec<u32> = vec![0; (WINDOW_WIDTH * WINDOW_HEIGHT) as usize];
     let fb_width = WINDOW_WIDTH as usize;
     let color = color::Color::red();
-    for face in &testmodel.faces {
+    for t in 0..testmodel.primitive_count() {
+        let verts = testmodel.primitive_vertices(t);
         for i in 0..3 {
             let (x0, y0) = utils::screen_to_image_space(
-                face.verts[i % 3].pos.x,
-                face.verts[i % 3].pos.y,
+                verts[i % 3].pos.x,
+                verts[i % 3].pos.y,
                 WINDOW_WIDTH,
                 WINDOW_HEIGHT,
             );
             let (x1, y1) = utils::screen_to_image_space(
-                face.verts[(i + 1) % 3].pos.x,
-                face.verts[(i + 1) % 3].pos.y,
+                verts[(i + 1) % 3].pos.x,
+                verts[(i + 1) % 3].pos.y,
                 WINDOW_WIDTH,
                 WINDOW_HEIGHT,
             );
@@ -81,7 +86,7 @@ fn test_lines_iter() {
         &fb,
         WINDOW_WIDTH,
         WINDOW_HEIGHT,
-    );
+    ).unwrap();
 }
 
 #[bench]
@@ -122,7 +127,7 @@ fn bench_triangle(b: &mut Bencher) {
         &fb,
         WINDOW_WIDTH,
         WINDOW_HEIGHT,
-    );
+    ).unwrap();
 }
 
 #[bench]
@@ -137,10 +142,8 @@ fn bench_triangle_iter(b: &mut Bencher) {
 
     b.iter(|| {
         let triangle = triangle::TriangleIterator::new(&tri);
-        for line in triangle {
-            for point in line {
-                fb[utils::xy(point.0, point.1, fb_width)] = color.bgra();
-            }
+        for point in triangle {
+            fb[utils::xy(point.0, point.1, fb_width)] = color.bgra();
         }
     });
     utils::save_buffer_as_image(
@@ -148,52 +151,48 @@ fn bench_triangle_iter(b: &mut Bencher) {
         &fb,
         WINDOW_WIDTH,
         WINDOW_HEIGHT,
-    );
+    ).unwrap();
 }
 
 #[test]
 fn test_head() {
-    let mut graphics: gl::Gl = gl::Gl::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+    let mut graphics: gl::Gl = gl::Gl::with_samples(WINDOW_WIDTH, WINDOW_HEIGHT, 4);
+    graphics.set_framebuffer_format(target::Format::Srgba8);
+    graphics.set_rasterizer_state(gl::RasterizerState {
+        cull_mode: gl::CullMode::Back,
+        ..gl::RasterizerState::default()
+    });
 
-    let camera: Vector3<f32> = Vector3::new(2.0, 0.0, 3.0);
-    let camera_target: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);
-    let up: Vector3<f32> = Vector3::new(0.0, 1.0, 0.0);
+    let camera = camera::Camera::perspective(
+        Vector3::new(2.0, 0.0, 3.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        (45.0f32).to_radians(),
+        graphics.aspect_ratio(),
+    );
     let light_pos = Vector3::new(0.0, 0.0, 1.0);
 
-    let view = gl::view_matrix(camera, camera_target, up);
-    let mut projection: Matrix4<f32> = Matrix4::identity();
-    projection[2][3] = -0.5 / camera.z;
-
     let head_modelpath = Path::new("./content/african_head/african_head.obj");
     let head_model = model::Model::load(head_modelpath).unwrap();
 
     let head_diffuse_image = image::open("./content/african_head/african_head_diffuse.tga")
         .unwrap();
-    let head_diffuse_tex = sync::Arc::new(head_diffuse_image);
+    let head_diffuse_tex = sync::Arc::new(texture::Texture::srgb(&head_diffuse_image));
     let head_normals_image = image::open("./content/african_head/african_head_nm.tga").unwrap();
-    let head_normals_tex = sync::Arc::new(head_normals_image);
+    let head_normals_tex = sync::Arc::new(texture::Texture::new(&head_normals_image));
     let head_specular_image = image::open("./content/african_head/african_head_spec.tga").unwrap();
-    let head_specular_tex = sync::Arc::new(head_specular_image);
-
-    let mut vs_in: gl::VSInput = gl::VSInput::default();
-    vs_in.view = view;
-    vs_in.projection = projection;
-    vs_in.camera = camera;
-    vs_in.camera_target = camera_target;
+    let head_specular_tex = sync::Arc::new(texture::Texture::new(&head_specular_image));
 
-    let mut ps_in: gl::PSInput = gl::PSInput::default();
-    ps_in.textures.push(head_diffuse_tex.clone());
-    ps_in.textures.push(head_normals_tex.clone());
-    ps_in.textures.push(head_specular_tex.clone());
-    ps_in.light_pos = light_pos;
-    ps_in.cam_dir = camera - camera_target;
+    let mut uniforms = camera.uniforms();
+    uniforms.textures.push(head_diffuse_tex.clone());
+    uniforms.textures.push(head_normals_tex.clone());
+    uniforms.textures.push(head_specular_tex.clone());
+    uniforms.light_pos = light_pos;
 
     graphics.draw(
         &head_model,
-        shaders::simple_vertex,
-        vs_in,
-        shaders::spec_pixel,
-        ps_in.clone(),
+        &uniforms,
+        &shaders::simple_vertex,
+        &shaders::spec_pixel,
     );
 
     let ei_modelpath = Path::new("./content/african_head/african_head_eye_inner.obj");
@@ -203,64 +202,139 @@ fn test_head() {
         "./content/african_head/african_head_eye_inner_diffuse\
                                         2.tga",
     ).unwrap();
-    let ei_diffuse_tex = sync::Arc::new(ei_diffuse_image);
+    let ei_diffuse_tex = sync::Arc::new(texture::Texture::srgb(&ei_diffuse_image));
     let ei_normals_image = image::open("./content/african_head/african_head_eye_inner_nm.tga")
         .unwrap();
-    let ei_normals_tex = sync::Arc::new(ei_normals_image);
+    let ei_normals_tex = sync::Arc::new(texture::Texture::new(&ei_normals_image));
     let ei_specular_image = image::open(
         "./content/african_head/african_head_eye_inner_spec.\
                                          tga",
     ).unwrap();
-    let ei_specular_tex = sync::Arc::new(ei_specular_image);
+    let ei_specular_tex = sync::Arc::new(texture::Texture::new(&ei_specular_image));
 
-    ps_in.textures.clear();
-    ps_in.textures.push(ei_diffuse_tex);
-    ps_in.textures.push(ei_normals_tex);
-    ps_in.textures.push(ei_specular_tex);
+    uniforms.textures.clear();
+    uniforms.textures.push(ei_diffuse_tex);
+    uniforms.textures.push(ei_normals_tex);
+    uniforms.textures.push(ei_specular_tex);
 
     graphics.draw(
         &ei_model,
-        shaders::simple_vertex,
-        vs_in,
-        shaders::spec_pixel,
-        ps_in,
+        &uniforms,
+        &shaders::simple_vertex,
+        &shaders::spec_pixel,
     );
 
-    graphics.save_framebuffer_as_image(Path::new("./test_output/test_head.png"));
+    graphics
+        .save_framebuffer_as_image(Path::new("./test_output/test_head.png"))
+        .unwrap();
 }
 
 fn _test_monkey() {
     let mut graphics: gl::Gl = gl::Gl::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+    graphics.set_rasterizer_state(gl::RasterizerState {
+        cull_mode: gl::CullMode::Back,
+        ..gl::RasterizerState::default()
+    });
 
-    let camera: Vector3<f32> = Vector3::new(2.0, 0.0, 3.0);
-    let camera_target: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);
-    let up: Vector3<f32> = Vector3::new(0.0, 1.0, 0.0);
+    let camera = camera::Camera::perspective(
+        Vector3::new(2.0, 0.0, 3.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        (45.0f32).to_radians(),
+        graphics.aspect_ratio(),
+    );
     let light_pos = Vector3::new(0.0, 0.0, 1.0);
 
-    let view = gl::view_matrix(camera, camera_target, up);
-    let mut projection: Matrix4<f32> = Matrix4::identity();
-    projection[2][3] = -0.5 / camera.z;
-
     let modelpath = Path::new("./content/monkey.obj");
     let model = model::Model::load(modelpath).unwrap();
 
-    let mut vs_in: gl::VSInput = gl::VSInput::default();
-    vs_in.view = view;
-    vs_in.projection = projection;
-    vs_in.camera = camera;
-    vs_in.camera_target = camera_target;
+    let mut uniforms = camera.uniforms();
+    uniforms.light_pos = light_pos;
+
+    graphics.draw(
+        &model,
+        &uniforms,
+        &shaders::simple_vertex,
+        &shaders::simple_pixel,
+    );
+
+    graphics
+        .save_framebuffer_as_image(Path::new("./test_output/test_monkey.png"))
+        .unwrap();
+}
+
+#[test]
+fn test_camera_inside_model() {
+    // Camera sits inside of the monkey head, so most of the triangles cross the near plane or
+    // extend past the viewport edges and have to be clipped.
+    let mut graphics: gl::Gl = gl::Gl::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+
+    let camera = camera::Camera::perspective(
+        Vector3::new(0.05, 0.02, 0.1),
+        Vector3::new(0.0, 0.0, 0.0),
+        (90.0f32).to_radians(),
+        graphics.aspect_ratio(),
+    );
+
+    let modelpath = Path::new("./content/monkey.obj");
+    let model = model::Model::load(modelpath).unwrap();
 
-    let mut ps_in: gl::PSInput = gl::PSInput::default();
-    ps_in.light_pos = light_pos;
-    ps_in.cam_dir = camera - camera_target;
+    let mut uniforms = camera.uniforms();
+    uniforms.light_pos = Vector3::new(0.0, 0.0, 1.0);
 
     graphics.draw(
         &model,
-        shaders::simple_vertex,
-        vs_in,
-        shaders::simple_pixel,
-        ps_in,
+        &uniforms,
+        &shaders::simple_vertex,
+        &shaders::simple_pixel,
     );
+    let written = graphics.framebuffer().iter().filter(|&&v| v != 0).count();
+    assert!(written > 0, "clipped model wrote no pixels");
 
-    graphics.save_framebuffer_as_image(Path::new("./test_output/test_monkey.png"));
+    // Triangles fully behind the camera are clipped away completely.
+    let model_view = uniforms.view * uniforms.model;
+    let behind: Vec<u32> = model
+        .indices
+        .chunks(3)
+        .filter(|triangle| {
+            triangle.iter().all(|&i| {
+                (model_view * model.vertices[i as usize].pos.extend(1.0)).z > 0.0
+            })
+        })
+        .flat_map(|triangle| triangle.iter().cloned())
+        .collect();
+    assert!(!behind.is_empty());
+    let behind = model::Model::new(model.vertices.clone(), behind);
+    let mut graphics: gl::Gl = gl::Gl::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+    graphics.draw(
+        &behind,
+        &uniforms,
+        &shaders::simple_vertex,
+        &shaders::simple_pixel,
+    );
+    assert!(graphics.framebuffer().iter().all(|&v| v == 0));
+    assert!(graphics.depth_buffer().iter().all(|&z| z == 1.0));
+}
+
+#[bench]
+fn bench_draw_monkey(b: &mut Bencher) {
+    let mut graphics: gl::Gl = gl::Gl::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+    let camera = camera::Camera::perspective(
+        Vector3::new(2.0, 0.0, 3.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        (45.0f32).to_radians(),
+        graphics.aspect_ratio(),
+    );
+    let model = model::Model::load(Path::new("./content/monkey.obj")).unwrap();
+
+    let mut uniforms = camera.uniforms();
+    uniforms.light_pos = Vector3::new(0.0, 0.0, 1.0);
+
+    b.iter(|| {
+        graphics.draw(
+            &model,
+            &uniforms,
+            &shaders::simple_vertex,
+            &shaders::simple_pixel,
+        )
+    });
 }
@@ -0,0 +1,662 @@
+//! Textures with mip chains and samplers filtering them.
+//!
+//! Mip levels are generated once when the texture is created, every level is half the size of
+//! the previous one down to 1x1. Sampler chooses the level from level of detail given by the
+//! shader, or computed from texcoord derivatives in `Sampler::sample_grad`.
+use std::path;
+use image::{self, GenericImage, Pixel};
+use cgmath::*;
+use color;
+use utils;
+
+
+/// How texels are filtered when sampling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Nearest texel of the nearest mip level.
+    Nearest,
+    /// Weighted average of four nearest texels of the nearest mip level.
+    Bilinear,
+    /// Bilinear samples of the two nearest mip levels blended by level of detail.
+    Trilinear,
+}
+
+
+/// How texcoords outside of the 0 - 1 range are mapped back to the texture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressMode {
+    /// Texture is tiled.
+    Repeat,
+    /// Texture is tiled with every other tile flipped, so the tiles meet with the same texels.
+    MirroredRepeat,
+    /// Texels on the edge are stretched out.
+    ClampToEdge,
+    /// `Sampler::border_color` is used outside of the texture.
+    ClampToBorder,
+}
+
+impl AddressMode {
+    /// Texel coordinate used for `coord` on axis with `size` texels, `None` for the border.
+    #[inline]
+    fn apply(&self, coord: i64, size: i64) -> Option<i64> {
+        match *self {
+            AddressMode::Repeat => Some(((coord % size) + size) % size),
+            AddressMode::MirroredRepeat => {
+                let period = size * 2;
+                let coord = ((coord % period) + period) % period;
+                Some(if coord < size { coord } else { period - 1 - coord })
+            }
+            AddressMode::ClampToEdge => Some(coord.max(0).min(size - 1)),
+            AddressMode::ClampToBorder if coord >= 0 && coord < size => Some(coord),
+            AddressMode::ClampToBorder => None,
+        }
+    }
+}
+
+
+/// How texel colors are stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Four 8 bit channels packed as `color::v4_as_value` does.
+    Rgba8,
+    /// Four 32 bit floats, four times the memory of `Rgba8` but without any precision loss in
+    /// the mip levels.
+    Rgba32F,
+}
+
+/// Order in which texels of a mip level are stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// Row after row.
+    Linear,
+    /// In 4x4 blocks stored one after another, so texels close to each other in both
+    /// directions share cache lines.
+    Tiled,
+}
+
+/// Color space the colors of a texture image are in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    /// Colors are used as they are, for data like normal maps.
+    Linear,
+    /// Colors are sRGB encoded, as in most color images, and get decoded to linear space when
+    /// sampled. Mip levels are averaged in linear space too.
+    Srgb,
+}
+
+/// Width and height of the texel blocks of `Layout::Tiled`.
+const TEXEL_TILE_SIZE: u32 = 4;
+
+
+/// Texels of one mip level, already decoded so sampling doesn't do any per texel conversions
+/// besides unpacking of `Format::Rgba8`.
+#[derive(Debug, Clone)]
+enum Texels {
+    Rgba8(Vec<u32>),
+    /// sRGB encoded `Format::Rgba8` texels, decoded to linear by `color::srgb_value_as_v4`.
+    Srgba8(Vec<u32>),
+    /// Float texels are always stored linear.
+    Rgba32F(Vec<Vector4<f32>>),
+}
+
+#[derive(Debug, Clone)]
+struct Level {
+    width: u32,
+    height: u32,
+    layout: Layout,
+    /// Number of texel blocks in a row for `Layout::Tiled`.
+    tiles_x: u32,
+    texels: Texels,
+}
+
+impl Level {
+    fn new(
+        width: u32,
+        height: u32,
+        format: Format,
+        layout: Layout,
+        color_space: ColorSpace,
+    ) -> Level {
+        let tiles_x = (width + TEXEL_TILE_SIZE - 1) / TEXEL_TILE_SIZE;
+        let tiles_y = (height + TEXEL_TILE_SIZE - 1) / TEXEL_TILE_SIZE;
+        let len = match layout {
+            Layout::Linear => (width * height) as usize,
+            Layout::Tiled => (tiles_x * tiles_y * TEXEL_TILE_SIZE * TEXEL_TILE_SIZE) as usize,
+        };
+        Level {
+            width: width,
+            height: height,
+            layout: layout,
+            tiles_x: tiles_x,
+            texels: match (format, color_space) {
+                (Format::Rgba8, ColorSpace::Linear) => Texels::Rgba8(vec![0; len]),
+                (Format::Rgba8, ColorSpace::Srgb) => Texels::Srgba8(vec![0; len]),
+                (Format::Rgba32F, _) => {
+                    Texels::Rgba32F(vec![Vector4::new(0.0, 0.0, 0.0, 0.0); len])
+                }
+            },
+        }
+    }
+
+    #[inline]
+    fn index(&self, x: u32, y: u32) -> usize {
+        match self.layout {
+            Layout::Linear => (x + y * self.width) as usize,
+            Layout::Tiled => {
+                let tile = x / TEXEL_TILE_SIZE + (y / TEXEL_TILE_SIZE) * self.tiles_x;
+                let (tx, ty) = (x % TEXEL_TILE_SIZE, y % TEXEL_TILE_SIZE);
+                (tile * TEXEL_TILE_SIZE * TEXEL_TILE_SIZE + tx + ty * TEXEL_TILE_SIZE) as usize
+            }
+        }
+    }
+
+    #[inline]
+    fn get(&self, x: u32, y: u32) -> Vector4<f32> {
+        let i = self.index(x, y);
+        match self.texels {
+            Texels::Rgba8(ref texels) => color::value_as_v4(texels[i]),
+            Texels::Srgba8(ref texels) => color::srgb_value_as_v4(texels[i]),
+            Texels::Rgba32F(ref texels) => texels[i],
+        }
+    }
+
+    fn set(&mut self, x: u32, y: u32, value: Vector4<f32>) {
+        let i = self.index(x, y);
+        match self.texels {
+            Texels::Rgba8(ref mut texels) => {
+                texels[i] = color::v4_as_value(utils::saturate_v4(value))
+            }
+            Texels::Srgba8(ref mut texels) => texels[i] = color::v4_as_srgb_value(value),
+            Texels::Rgba32F(ref mut texels) => texels[i] = value,
+        }
+    }
+
+    /// Half sized level where every texel is the average of the texels it covers. With odd
+    /// sizes the last texel of every row or column also covers the extra one, averaging three
+    /// texels along that axis.
+    fn downsample(&self, format: Format, color_space: ColorSpace) -> Level {
+        let (width, height) = (self.width, self.height);
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut next = Level::new(next_width, next_height, format, self.layout, color_space);
+        let span = |i: u32, size: u32, next_size: u32| {
+            let end = if i == next_size - 1 { size } else { i * 2 + 2 };
+            (i * 2)..end
+        };
+        for y in 0..next.height {
+            for x in 0..next.width {
+                let mut sum = Vector4::new(0.0, 0.0, 0.0, 0.0);
+                let mut count = 0;
+                for sy in span(y, height, next_height) {
+                    for sx in span(x, width, next_width) {
+                        sum = sum + self.get(sx, sy);
+                        count += 1;
+                    }
+                }
+                next.set(x, y, sum / count as f32);
+            }
+        }
+        next
+    }
+}
+
+
+/// Image together with its mip levels, colors are stored with channels in 0 - 1 range.
+///
+/// Image is decoded only once when the texture is created, sampling reads the texels
+/// directly in the chosen `Format` and `Layout`.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    format: Format,
+    color_space: ColorSpace,
+    levels: Vec<Level>,
+}
+
+impl Texture {
+    /// Create `Format::Rgba8` texture from linear `image` and generate its mip levels.
+    pub fn new(image: &image::DynamicImage) -> Texture {
+        Texture::with_format(image, Format::Rgba8, Layout::Linear, ColorSpace::Linear)
+    }
+
+    /// Create `Format::Rgba8` texture from sRGB encoded `image` and generate its mip levels.
+    pub fn srgb(image: &image::DynamicImage) -> Texture {
+        Texture::with_format(image, Format::Rgba8, Layout::Linear, ColorSpace::Srgb)
+    }
+
+    /// Create texture from `image` with colors in `color_space`, stored in `format` and
+    /// `layout`, and generate its mip levels.
+    ///
+    /// Panics if the image is empty, so that sampling never has to deal with zero sized levels.
+    pub fn with_format(
+        image: &image::DynamicImage,
+        format: Format,
+        layout: Layout,
+        color_space: ColorSpace,
+    ) -> Texture {
+        let image = image.to_rgba();
+        assert!(
+            image.width() > 0 && image.height() > 0,
+            "texture image can't be empty, it's {}x{}",
+            image.width(),
+            image.height()
+        );
+        let mut level = Level::new(image.width(), image.height(), format, layout, color_space);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let channels = pixel.channels();
+            let channel = |i: usize| match color_space {
+                ColorSpace::Linear => channels[i] as f32 / 255.0,
+                ColorSpace::Srgb => color::srgb8_to_linear(channels[i]),
+            };
+            // Alpha is linear in both color spaces.
+            let alpha = channels[3] as f32 / 255.0;
+            let color = Vector4::new(channel(0), channel(1), channel(2), alpha);
+            level.set(x, y, color);
+        }
+
+        let mut levels = vec![level];
+        loop {
+            let next = match levels.last() {
+                Some(level) if level.width > 1 || level.height > 1 => {
+                    level.downsample(format, color_space)
+                }
+                _ => break,
+            };
+            levels.push(next);
+        }
+        Texture {
+            format: format,
+            color_space: color_space,
+            levels: levels,
+        }
+    }
+
+    /// Load image from `path` as a texture, see `try_new`.
+    pub fn open(path: &path::Path) -> image::ImageResult<Texture> {
+        let image = try!(image::open(path));
+        Texture::try_new(&image)
+    }
+
+    /// Like `new`, but empty images are rejected with `ImageError::DimensionError` instead of
+    /// panicking.
+    pub fn try_new(image: &image::DynamicImage) -> image::ImageResult<Texture> {
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return Err(image::ImageError::DimensionError);
+        }
+        Ok(Texture::new(image))
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.levels[0].layout
+    }
+
+    /// Number of mip levels, including the full size one.
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Width and height of mip level `level`.
+    #[inline]
+    pub fn dimensions(&self, level: usize) -> (u32, u32) {
+        (self.levels[level].width, self.levels[level].height)
+    }
+
+    /// Color of texel `(x, y)` of mip level `level`, in linear space for both color spaces.
+    #[inline]
+    pub fn texel(&self, level: usize, x: u32, y: u32) -> color::LinearRgba {
+        color::LinearRgba(self.levels[level].get(x, y))
+    }
+}
+
+
+/// Describes how textures are sampled.
+#[derive(Debug, Clone, Copy)]
+pub struct Sampler {
+    pub filter: Filter,
+    /// Address mode of the horizontal texcoord.
+    pub address_u: AddressMode,
+    /// Address mode of the vertical texcoord.
+    pub address_v: AddressMode,
+    /// Linear color outside of the texture for `AddressMode::ClampToBorder`.
+    pub border_color: Vector4<f32>,
+    /// Highest number of samples taken along the longer axis of the pixel footprint by
+    /// `sample_grad`, 1 disables anisotropic filtering.
+    pub max_anisotropy: u32,
+}
+
+impl Default for Sampler {
+    fn default() -> Sampler {
+        Sampler {
+            filter: Filter::Trilinear,
+            address_u: AddressMode::Repeat,
+            address_v: AddressMode::Repeat,
+            border_color: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            max_anisotropy: 1,
+        }
+    }
+}
+
+impl Sampler {
+    /// Sample the full size level of `texture` at `texcoord`.
+    #[inline]
+    pub fn sample(&self, texture: &Texture, texcoord: Vector2<f32>) -> color::LinearRgba {
+        self.sample_lod(texture, texcoord, 0.0)
+    }
+
+    /// Sample `texture` at `texcoord` with level of detail `lod`, where 0 is the full size
+    /// level and every next whole number halves it.
+    ///
+    /// Any texcoord is valid, infinite and NaN ones are treated as 0.
+    pub fn sample_lod(
+        &self,
+        texture: &Texture,
+        texcoord: Vector2<f32>,
+        lod: f32,
+    ) -> color::LinearRgba {
+        color::LinearRgba(self.filter_lod(texture, texcoord, lod))
+    }
+
+    /// Filtered color of `texture` at `texcoord` for level of detail `lod`, see `sample_lod`.
+    fn filter_lod(&self, texture: &Texture, texcoord: Vector2<f32>, lod: f32) -> Vector4<f32> {
+        let texcoord = Vector2::new(finite_or_zero(texcoord.x), finite_or_zero(texcoord.y));
+        let max_lod = (texture.level_count() - 1) as f32;
+        let lod = lod.max(0.0).min(max_lod);
+        match self.filter {
+            Filter::Nearest => self.nearest(texture, lod.round() as usize, texcoord),
+            Filter::Bilinear => self.bilinear(texture, lod.round() as usize, texcoord),
+            Filter::Trilinear => {
+                let level = lod.floor();
+                let t = lod - level;
+                let level = level as usize;
+                let near = self.bilinear(texture, level, texcoord);
+                if t == 0.0 {
+                    return near;
+                }
+                near.lerp(self.bilinear(texture, level + 1, texcoord), t)
+            }
+        }
+    }
+
+    /// Sample `texture` at `texcoord` with level of detail chosen from the change of texcoord
+    /// between neighbouring pixels along x (`ddx`) and y (`ddy`).
+    ///
+    /// With anisotropic filtering level is chosen by the shorter axis of the pixel footprint
+    /// and multiple samples are averaged along the longer one.
+    pub fn sample_grad(
+        &self,
+        texture: &Texture,
+        texcoord: Vector2<f32>,
+        ddx: Vector2<f32>,
+        ddy: Vector2<f32>,
+    ) -> color::LinearRgba {
+        let (width, height) = texture.dimensions(0);
+        let size = Vector2::new(width as f32, height as f32);
+        let (x_axis, y_axis) = (ddx.mul_element_wise(size), ddy.mul_element_wise(size));
+        let (x_length, y_length) = (x_axis.magnitude(), y_axis.magnitude());
+        let (major, major_length, minor_length) = if x_length >= y_length {
+            (ddx, x_length, y_length)
+        } else {
+            (ddy, y_length, x_length)
+        };
+
+        let max_anisotropy = self.max_anisotropy.max(1) as f32;
+        let ratio = (major_length / minor_length.max(1e-8)).min(max_anisotropy);
+        let count = ratio.ceil().max(1.0) as u32;
+        if count == 1 {
+            return self.sample_lod(texture, texcoord, major_length.max(1e-8).log2());
+        }
+
+        // Samples are spread evenly over the longer axis, each one covering its part of it.
+        let lod = (major_length / count as f32).max(1e-8).log2();
+        let mut sum = Vector4::new(0.0, 0.0, 0.0, 0.0);
+        for i in 0..count {
+            let offset = (i as f32 + 0.5) / count as f32 - 0.5;
+            sum = sum + self.filter_lod(texture, texcoord + major * offset, lod);
+        }
+        color::LinearRgba(sum / count as f32)
+    }
+
+    /// Color of texel `(x, y)` of mip level `level` after applying address modes.
+    #[inline]
+    fn fetch(&self, texture: &Texture, level: usize, x: i64, y: i64) -> Vector4<f32> {
+        let (width, height) = texture.dimensions(level);
+        let x = self.address_u.apply(x, width as i64);
+        let y = self.address_v.apply(y, height as i64);
+        match (x, y) {
+            (Some(x), Some(y)) => texture.texel(level, x as u32, y as u32).0,
+            _ => self.border_color,
+        }
+    }
+
+    fn nearest(&self, texture: &Texture, level: usize, texcoord: Vector2<f32>) -> Vector4<f32> {
+        let (width, height) = texture.dimensions(level);
+        let x = texel_coord((texcoord.x * width as f32).floor());
+        let y = texel_coord((texcoord.y * height as f32).floor());
+        self.fetch(texture, level, x, y)
+    }
+
+    fn bilinear(&self, texture: &Texture, level: usize, texcoord: Vector2<f32>) -> Vector4<f32> {
+        let (width, height) = texture.dimensions(level);
+        let x = texcoord.x * width as f32 - 0.5;
+        let y = texcoord.y * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (texel_coord(x0), texel_coord(y0));
+        let top = self.fetch(texture, level, x0, y0)
+            .lerp(self.fetch(texture, level, x0 + 1, y0), tx);
+        let bottom = self.fetch(texture, level, x0, y0 + 1)
+            .lerp(self.fetch(texture, level, x0 + 1, y0 + 1), tx);
+        top.lerp(bottom, ty)
+    }
+}
+
+#[inline]
+fn finite_or_zero(v: f32) -> f32 {
+    if v.is_finite() { v } else { 0.0 }
+}
+
+/// Whole texel coordinate `v` as integer, limited to a range which can't overflow.
+#[inline]
+fn texel_coord(v: f32) -> i64 {
+    v.max(-1.0e9).min(1.0e9) as i64
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: color::LinearRgba, b: Vector4<f32>) {
+        let a = a.0;
+        assert!((a - b).magnitude() < 1e-2, "{:?} != {:?}", a, b);
+    }
+
+    /// 4x4 texture with black and white texel columns.
+    fn stripes() -> Texture {
+        let image = image::RgbaImage::from_fn(4, 4, |x, _| if x % 2 == 0 {
+            image::Rgba([0, 0, 0, 255])
+        } else {
+            image::Rgba([255, 255, 255, 255])
+        });
+        Texture::new(&image::DynamicImage::ImageRgba8(image))
+    }
+
+    #[test]
+    fn test_mip_levels() {
+        let texture = stripes();
+        assert_eq!(texture.level_count(), 3);
+        assert_eq!(texture.dimensions(1), (2, 2));
+        assert_eq!(texture.dimensions(2), (1, 1));
+        let gray = Vector4::new(0.5, 0.5, 0.5, 1.0);
+        assert_close(texture.texel(1, 0, 0), gray);
+        assert_close(texture.texel(2, 0, 0), gray);
+    }
+
+    #[test]
+    fn test_mip_odd_size() {
+        // Last column is white, it has to end up in the last texel of every smaller level.
+        let image = image::RgbaImage::from_fn(5, 5, |x, _| if x == 4 {
+            image::Rgba([255, 255, 255, 255])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        });
+        let texture = Texture::new(&image::DynamicImage::ImageRgba8(image));
+        assert_eq!(texture.dimensions(1), (2, 2));
+        let third = Vector4::new(1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0, 1.0);
+        assert_close(texture.texel(1, 0, 1), Vector4::new(0.0, 0.0, 0.0, 1.0));
+        assert_close(texture.texel(1, 1, 1), third);
+        let sixth = Vector4::new(1.0 / 6.0, 1.0 / 6.0, 1.0 / 6.0, 1.0);
+        assert_close(texture.texel(2, 0, 0), sixth);
+    }
+
+    #[test]
+    #[should_panic(expected = "texture image can't be empty")]
+    fn test_empty_image() {
+        Texture::new(&image::DynamicImage::ImageRgba8(image::RgbaImage::new(0, 4)));
+    }
+
+    #[test]
+    fn test_try_new_empty_image() {
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::new(4, 0));
+        match Texture::try_new(&image) {
+            Err(image::ImageError::DimensionError) => {}
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("texture was created from an empty image"),
+        }
+        assert!(Texture::try_new(&image::DynamicImage::new_rgba8(1, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_filters() {
+        let texture = stripes();
+        let between = Vector2::new(0.25, 0.5);
+        let black = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let gray = Vector4::new(0.5, 0.5, 0.5, 1.0);
+        let nearest = Sampler {
+            filter: Filter::Nearest,
+            ..Sampler::default()
+        };
+        let bilinear = Sampler {
+            filter: Filter::Bilinear,
+            ..Sampler::default()
+        };
+        assert_close(nearest.sample(&texture, Vector2::new(0.1, 0.5)), black);
+        assert_close(bilinear.sample(&texture, between), gray);
+
+        // Halfway between the full size level and the averaged one.
+        let trilinear = Sampler::default();
+        let center = Vector2::new(0.125, 0.5);
+        assert_close(trilinear.sample_lod(&texture, center, 0.5), black.lerp(gray, 0.5));
+    }
+
+    #[test]
+    fn test_anisotropic() {
+        let texture = stripes();
+        let center = Vector2::new(0.125, 0.5);
+        // Footprint is one texel wide and four texels tall, so isotropic filtering blurs the
+        // columns together while anisotropic one keeps the full size level.
+        let (ddx, ddy) = (Vector2::new(0.25, 0.0), Vector2::new(0.0, 1.0));
+        let isotropic = Sampler::default().sample_grad(&texture, center, ddx, ddy);
+        assert_close(isotropic, Vector4::new(0.5, 0.5, 0.5, 1.0));
+        let anisotropic = Sampler {
+            max_anisotropy: 4,
+            ..Sampler::default()
+        };
+        let sample = anisotropic.sample_grad(&texture, center, ddx, ddy);
+        assert_close(sample, Vector4::new(0.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_address_modes() {
+        // Black texel on the left, white on the right.
+        let image = image::RgbaImage::from_fn(2, 1, |x, _| {
+            let v = x as u8 * 255;
+            image::Rgba([v, v, v, 255])
+        });
+        let texture = Texture::new(&image::DynamicImage::ImageRgba8(image));
+        let black = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let white = Vector4::new(1.0, 1.0, 1.0, 1.0);
+        let sample = |mode: AddressMode, u: f32| {
+            let sampler = Sampler {
+                filter: Filter::Nearest,
+                address_u: mode,
+                border_color: Vector4::new(1.0, 0.0, 0.0, 1.0),
+                ..Sampler::default()
+            };
+            sampler.sample(&texture, Vector2::new(u, 0.5))
+        };
+        assert_close(sample(AddressMode::Repeat, 1.25), black);
+        assert_close(sample(AddressMode::Repeat, -0.25), white);
+        assert_close(sample(AddressMode::MirroredRepeat, 1.25), white);
+        assert_close(sample(AddressMode::MirroredRepeat, -0.25), black);
+        assert_close(sample(AddressMode::ClampToEdge, 1.25), white);
+        assert_close(sample(AddressMode::ClampToEdge, -0.25), black);
+        assert_close(sample(AddressMode::ClampToBorder, 1.25), Vector4::new(1.0, 0.0, 0.0, 1.0));
+
+        // Vertical texcoord uses its own mode.
+        let sampler = Sampler {
+            address_v: AddressMode::ClampToBorder,
+            ..Sampler::default()
+        };
+        let border = sampler.sample(&texture, Vector2::new(0.25, 3.0));
+        assert_close(border, sampler.border_color);
+
+        for &v in &[::std::f32::NAN, ::std::f32::INFINITY, -1.0e30] {
+            for &mode in &[AddressMode::Repeat, AddressMode::MirroredRepeat] {
+                let color = sample(mode, v);
+                assert!(color.0 == black || color.0 == white);
+            }
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        // Size which isn't a multiple of the texel blocks.
+        let image = image::RgbaImage::from_fn(7, 5, |x, y| {
+            image::Rgba([x as u8 * 36, y as u8 * 60, 255 - x as u8 * 20, 255])
+        });
+        let image = image::DynamicImage::ImageRgba8(image);
+        let linear = Texture::new(&image);
+        let layout = Layout::Tiled;
+        let tiled = Texture::with_format(&image, Format::Rgba32F, layout, ColorSpace::Linear);
+        assert_eq!(tiled.level_count(), linear.level_count());
+        for level in 0..linear.level_count() {
+            let (width, height) = linear.dimensions(level);
+            assert_eq!(tiled.dimensions(level), (width, height));
+            for y in 0..height {
+                for x in 0..width {
+                    assert_close(tiled.texel(level, x, y), linear.texel(level, x, y).0);
+                }
+            }
+        }
+        assert_close(linear.texel(0, 6, 4), Vector4::new(216.0, 240.0, 135.0, 255.0) / 255.0);
+    }
+
+    #[test]
+    fn test_srgb() {
+        let image = image::RgbaImage::from_fn(2, 2, |x, y| if (x + y) % 2 == 0 {
+            image::Rgba([0, 0, 0, 255])
+        } else {
+            image::Rgba([255, 255, 255, 255])
+        });
+        let image = image::DynamicImage::ImageRgba8(image);
+        let gray = Vector4::new(0.5, 0.5, 0.5, 1.0);
+        for &format in &[Format::Rgba8, Format::Rgba32F] {
+            let texture = Texture::with_format(&image, format, Layout::Linear, ColorSpace::Srgb);
+            // Averaged in linear space, so the level is half as bright as the full size one.
+            assert_close(texture.texel(1, 0, 0), gray);
+        }
+
+        // Texel in the middle of the sRGB range is much darker in linear space.
+        let image = image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 128, 255]));
+        let texture = Texture::srgb(&image::DynamicImage::ImageRgba8(image));
+        assert_close(texture.texel(0, 0, 0), Vector4::new(0.2158, 0.2158, 0.2158, 1.0));
+    }
+}
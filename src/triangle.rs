@@ -1,5 +1,4 @@
 use cgmath::*;
-use line;
 use color;
 use utils;
 
@@ -28,125 +27,234 @@ pub fn barycentric(point: Vector2<f32>, tri: &[Vector3<f32>]) -> Option<Vector3<
 }
 
 
-/// Returns bounding box as tuple `(min_x, min_y, max_x, max_y)`
-/// # Panics
-/// * Not being able to find max or min value.
-/// * Or anything else really, full of unwrap.
-fn bounding_box(positions: &[Vector2<u32>]) -> (u32, u32, u32, u32) {
-    let min_x = positions.iter().map(|pos| pos.x).min().unwrap();
-    let min_y = positions.iter().map(|pos| pos.y).min().unwrap();
-    let max_x = positions.iter().map(|pos| pos.x).max().unwrap();
-    let max_y = positions.iter().map(|pos| pos.y).max().unwrap();
-    (min_x, min_y, max_x, max_y)
-}
+/// Number of fractional bits used for fixed point vertex positions by default.
+pub const DEFAULT_SUBPIXEL_BITS: u32 = 8;
+
+/// Largest supported number of fractional bits. Edge functions have to fit in 64 bits, which
+/// with this precision still allows for viewports reaching 16383 pixels, larger ones have to
+/// use fewer bits as given by `max_subpixel_bits`.
+pub const MAX_SUBPIXEL_BITS: u32 = 16;
 
+/// Fixed point coordinates have to stay below this value, so that products of two of them
+/// and sums of those in edge functions fit in 64 bits.
+const MAX_FIXED_POINT: u64 = 1 << 30;
 
-fn naive_point_in_triangle(point: (usize, usize), triangle: &[Vector2<u32>]) -> bool {
-    let p0 = Vector2::<f32>::new(triangle[0].x as f32, triangle[0].y as f32);
-    let p1 = Vector2::<f32>::new(triangle[1].x as f32, triangle[1].y as f32);
-    let p2 = Vector2::<f32>::new(triangle[2].x as f32, triangle[2].y as f32);
-    let p = Vector2::<f32>::new(point.0 as f32, point.1 as f32);
+/// Largest number of fractional bits, up to `MAX_SUBPIXEL_BITS`, with which positions up to
+/// `extent` pixels from the origin still fit in fixed point. Zero if even one bit doesn't fit.
+pub fn max_subpixel_bits(extent: u32) -> u32 {
+    (1..MAX_SUBPIXEL_BITS + 1)
+        .rev()
+        .find(|&bits| (extent as u64 + 1) << bits <= MAX_FIXED_POINT)
+        .unwrap_or(0)
+}
 
-    let c0 = Vector3::<f32>::new(p2.x - p0.x, p1.x - p0.x, p0.x - p.x);
-    let c1 = Vector3::<f32>::new(p2.y - p0.y, p1.y - p0.y, p0.y - p.y);
-    let u = c0.cross(c1);
+/// Standard multisample patterns as sample offsets from the pixel center in 1/16 of a pixel.
+static SAMPLE_PATTERN_1: [(i32, i32); 1] = [(0, 0)];
+static SAMPLE_PATTERN_2: [(i32, i32); 2] = [(4, 4), (-4, -4)];
+static SAMPLE_PATTERN_4: [(i32, i32); 4] = [(-2, -6), (6, -2), (-6, 2), (2, 6)];
+static SAMPLE_PATTERN_8: [(i32, i32); 8] = [
+    (1, -3),
+    (-1, 3),
+    (5, 1),
+    (-3, -5),
+    (-5, 5),
+    (-7, -1),
+    (3, 7),
+    (7, -7),
+];
 
-    if u.z.abs() < 1.0 {
-        return false;
+/// Sample offsets used for `sample_count` samples per pixel. Supported counts are 1, 2, 4 and
+/// 8, others are rounded down to the nearest supported one, with 0 giving a single sample.
+pub fn sample_pattern(sample_count: u32) -> &'static [(i32, i32)] {
+    if sample_count >= 8 {
+        &SAMPLE_PATTERN_8
+    } else if sample_count >= 4 {
+        &SAMPLE_PATTERN_4
+    } else if sample_count >= 2 {
+        &SAMPLE_PATTERN_2
+    } else {
+        &SAMPLE_PATTERN_1
     }
+}
 
-    let r = Vector3::<f32>::new(1.0 - (u.x + u.y) / u.z, u.y / u.z, u.x / u.z);
 
-    r.x > 0.0 && r.y > 0.0 && r.z > 0.0
+/// Edge function `a * x + b * y + c` of one triangle edge in fixed point coordinates.
+/// Oriented so that it is positive on the inner side of the edge.
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    a: i64,
+    b: i64,
+    c: i64,
+    /// Zero for top and left edges, -1 for others, so that points lying exactly on an edge
+    /// belong only to the triangle for which that edge is a top or left one.
+    bias: i64,
 }
 
-const EPSILON: f32 = 0.01;
-const EPSILON_SQUARE: f32 = EPSILON * EPSILON;
-
-fn point_in_triangle_bounding_box(
-    x1: f32,
-    y1: f32,
-    x2: f32,
-    y2: f32,
-    x3: f32,
-    y3: f32,
-    point: (f32, f32),
-) -> bool {
-    let x = point.0;
-    let y = point.1;
-    let x_min: f32 = x1.min(x2.min(x3)) - EPSILON;
-    let x_max: f32 = x1.max(x2.max(x3)) + EPSILON;
-    let y_min: f32 = y1.min(y2.min(y3)) - EPSILON;
-    let y_max: f32 = y1.max(y2.max(y3)) + EPSILON;
-
-    !(x < x_min || x_max < x || y < y_min || y_max < y)
-}
+impl Edge {
+    fn new(v0: (i64, i64), v1: (i64, i64), orientation: i64) -> Edge {
+        let a = (v0.1 - v1.1) * orientation;
+        let b = (v1.0 - v0.0) * orientation;
+        let c = (v0.0 * v1.1 - v0.1 * v1.0) * orientation;
+        // With y growing downwards top edge is horizontal with the inside below it, while
+        // left edge has the inside on its right.
+        let is_top_left = a > 0 || (a == 0 && b > 0);
+        Edge {
+            a: a,
+            b: b,
+            c: c,
+            bias: if is_top_left { 0 } else { -1 },
+        }
+    }
 
-fn distance_square_point_to_segment(x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) -> f32 {
-    let p1_p2_square_length: f32 = (x2 - x1) * (x2 - x1) + (y2 - y1) * (y2 - y1);
-    let dot_product: f32 = ((x - x1) * (x2 - x1) + (y - y1) * (y2 - y1)) / p1_p2_square_length;
-    if dot_product < 0.0 {
-        (x - x1) * (x - x1) + (y - y1) * (y - y1)
-    } else if dot_product <= 1.0 {
-        let p_p1_square_length: f32 = (x1 - x) * (x1 - x) + (y1 - y) * (y1 - y);
-        p_p1_square_length - dot_product * dot_product * p1_p2_square_length
-    } else {
-        (x - x2) * (x - x2) + (y - y2) * (y - y2)
+    #[inline]
+    fn evaluate(&self, x: i64, y: i64) -> i64 {
+        self.a * x + self.b * y + self.c
     }
 }
 
-fn point_in_triangle(point: (usize, usize), triangle: &[Vector2<u32>]) -> bool {
-    let x1 = triangle[0].x as f32;
-    let y1 = triangle[0].y as f32;
-    let x2 = triangle[1].x as f32;
-    let y2 = triangle[1].y as f32;
-    let x3 = triangle[2].x as f32;
-    let y3 = triangle[2].y as f32;
-    let x = point.0 as f32;
-    let y = point.1 as f32;
 
-    if !point_in_triangle_bounding_box(x1, y1, x2, y2, x3, y3, (x, y)) {
-        return false;
+/// Triangle prepared for rasterization with fixed point edge functions.
+///
+/// Vertex positions are snapped to `1 / 2^subpixel_bits` of a pixel and pixels are sampled at
+/// their centers. Points on edges shared by two triangles are covered by exactly one of them
+/// following the top-left fill rule, so meshes are rasterized without gaps or overdraw.
+#[derive(Debug, Clone, Copy)]
+pub struct TriangleSetup {
+    /// Edges opposite to the first, second and third vertex.
+    edges: [Edge; 3],
+    /// Twice the area of triangle in fixed point units, always positive.
+    area: i64,
+    counter_clockwise: bool,
+    subpixel_bits: u32,
+    /// Pixels which may be covered, as `(min_x, min_y, max_x, max_y)`, inclusive.
+    pub bounding_box: (u32, u32, u32, u32),
+}
+
+impl TriangleSetup {
+    /// Set up triangle with screen space `positions` given in pixels. Only pixels inside of
+    /// `bounds` as `(min_x, min_y, max_x, max_y)`, inclusive, are ever covered.
+    /// Returns `None` for degenerate triangles or the ones not touching any pixel center.
+    pub fn new(
+        positions: &[Vector3<f32>],
+        subpixel_bits: u32,
+        bounds: (u32, u32, u32, u32),
+    ) -> Option<TriangleSetup> {
+        TriangleSetup::with_samples(positions, subpixel_bits, bounds, &SAMPLE_PATTERN_1)
     }
 
-    if naive_point_in_triangle(point, triangle) {
-        return true;
+    /// Set up triangle like `new`, with the bounding box covering every pixel which has any of
+    /// the `samples`, given as in `sample_pattern`, inside of the triangle.
+    pub fn with_samples(
+        positions: &[Vector3<f32>],
+        subpixel_bits: u32,
+        bounds: (u32, u32, u32, u32),
+        samples: &[(i32, i32)],
+    ) -> Option<TriangleSetup> {
+        assert!(
+            subpixel_bits >= 1 && subpixel_bits <= MAX_SUBPIXEL_BITS,
+            "subpixel bits: {}, are not in supported range",
+            subpixel_bits
+        );
+        let scale = (1 << subpixel_bits) as f32;
+        let fixed: Vec<(i64, i64)> = positions
+            .iter()
+            .map(|p| ((p.x * scale).round() as i64, (p.y * scale).round() as i64))
+            .collect();
+
+        let (v0, v1, v2) = (fixed[0], fixed[1], fixed[2]);
+        let signed_area = (v1.0 - v0.0) * (v2.1 - v0.1) - (v1.1 - v0.1) * (v2.0 - v0.0);
+        if signed_area == 0 {
+            return None;
+        }
+        // Cross product is negative for triangles which look counter clockwise on the screen,
+        // as y grows downwards.
+        let orientation = if signed_area < 0 { -1 } else { 1 };
+
+        // First and last pixel which samples can be inside of the triangle. Computed from the
+        // snapped positions, as those are the ones which decide coverage.
+        let one = scale as f64;
+        let min_ox = samples.iter().map(|s| s.0).min().unwrap_or(0) as f64 / 16.0;
+        let max_ox = samples.iter().map(|s| s.0).max().unwrap_or(0) as f64 / 16.0;
+        let min_oy = samples.iter().map(|s| s.1).min().unwrap_or(0) as f64 / 16.0;
+        let max_oy = samples.iter().map(|s| s.1).max().unwrap_or(0) as f64 / 16.0;
+        let first = |v: i64, o: f64, min: u32| ((v as f64 / one - 0.5 - o).ceil()).max(min as f64);
+        let last = |v: i64, o: f64, max: u32| ((v as f64 / one - 0.5 - o).floor()).min(max as f64);
+        let first_x = first(fixed.iter().map(|p| p.0).min().unwrap(), max_ox, bounds.0);
+        let first_y = first(fixed.iter().map(|p| p.1).min().unwrap(), max_oy, bounds.1);
+        let last_x = last(fixed.iter().map(|p| p.0).max().unwrap(), min_ox, bounds.2);
+        let last_y = last(fixed.iter().map(|p| p.1).max().unwrap(), min_oy, bounds.3);
+        if first_x > last_x || first_y > last_y {
+            return None;
+        }
+
+        Some(TriangleSetup {
+            edges: [
+                Edge::new(v1, v2, orientation),
+                Edge::new(v2, v0, orientation),
+                Edge::new(v0, v1, orientation),
+            ],
+            area: signed_area * orientation,
+            counter_clockwise: signed_area < 0,
+            subpixel_bits: subpixel_bits,
+            bounding_box: (first_x as u32, first_y as u32, last_x as u32, last_y as u32),
+        })
     }
-    if distance_square_point_to_segment(x1, y1, x2, y2, x, y) <= EPSILON_SQUARE {
-        return true;
+
+    /// True if triangle vertices appear in counter clockwise order on the screen.
+    pub fn is_counter_clockwise(&self) -> bool {
+        self.counter_clockwise
     }
-    if distance_square_point_to_segment(x2, y2, x3, y3, x, y) <= EPSILON_SQUARE {
-        return true;
+
+    /// Returns barycentric coordinates of the center of pixel `(x, y)` if it's covered.
+    #[inline]
+    pub fn coverage(&self, x: u32, y: u32) -> Option<Vector3<f32>> {
+        self.sample_coverage(x, y, (0, 0))
     }
-    if distance_square_point_to_segment(x3, y3, x1, y1, x, y) <= EPSILON_SQUARE {
-        return true;
+
+    /// Returns barycentric coordinates of the sample at `offset` from the center of pixel
+    /// `(x, y)` if it's covered. Offset is given in 1/16 of a pixel, as in `sample_pattern`.
+    #[inline]
+    pub fn sample_coverage(&self, x: u32, y: u32, offset: (i32, i32)) -> Option<Vector3<f32>> {
+        let (sx, sy) = self.sample_position(x, y, offset);
+        let w0 = self.edges[0].evaluate(sx, sy);
+        let w1 = self.edges[1].evaluate(sx, sy);
+        let w2 = self.edges[2].evaluate(sx, sy);
+        if w0 + self.edges[0].bias < 0 || w1 + self.edges[1].bias < 0 ||
+            w2 + self.edges[2].bias < 0
+        {
+            return None;
+        }
+        Some(self.weights(w0, w1, w2))
+    }
+
+    /// Returns barycentric coordinates of the center of pixel `(x, y)`, which lie outside of
+    /// 0 - 1 range when the center is not covered.
+    #[inline]
+    pub fn barycentric(&self, x: u32, y: u32) -> Vector3<f32> {
+        let (sx, sy) = self.sample_position(x, y, (0, 0));
+        self.weights(
+            self.edges[0].evaluate(sx, sy),
+            self.edges[1].evaluate(sx, sy),
+            self.edges[2].evaluate(sx, sy),
+        )
+    }
+
+    #[inline]
+    fn sample_position(&self, x: u32, y: u32, offset: (i32, i32)) -> (i64, i64) {
+        let half = 1i64 << (self.subpixel_bits - 1);
+        let ox = ((offset.0 as i64) << self.subpixel_bits) >> 4;
+        let oy = ((offset.1 as i64) << self.subpixel_bits) >> 4;
+        (
+            ((x as i64) << self.subpixel_bits) + half + ox,
+            ((y as i64) << self.subpixel_bits) + half + oy,
+        )
     }
-    false
-}
 
-#[test]
-fn test_point_in_triangle() {
-    let mut tri: Vec<Vector2<u32>> = Vec::with_capacity(3);
-    tri.push(Vector2::<u32>::new(245, 391));
-    tri.push(Vector2::<u32>::new(115, 200));
-    tri.push(Vector2::<u32>::new(306, 438));
-
-    let mut point = (234, 357);
-    assert!(point_in_triangle(point, tri.as_ref()));
-    point = (236, 277);
-    assert!(!point_in_triangle(point, tri.as_ref()));
-
-    tri.clear();
-    tri.push(Vector2::<u32>::new(375, 186));
-    tri.push(Vector2::<u32>::new(2, 257));
-    tri.push(Vector2::<u32>::new(483, 5));
-
-    point = (340, 110);
-    assert!(point_in_triangle(point, tri.as_ref()));
-    point = (288, 82);
-    assert!(!point_in_triangle(point, tri.as_ref()));
-    point = (375, 186);
-    assert!(point_in_triangle(point, tri.as_ref()));
+    #[inline]
+    fn weights(&self, w0: i64, w1: i64, w2: i64) -> Vector3<f32> {
+        let area = self.area as f32;
+        Vector3::new(w0 as f32 / area, w1 as f32 / area, w2 as f32 / area)
+    }
 }
 
 
@@ -157,51 +265,211 @@ pub fn draw(
     buffer: &mut [u32],
     buffer_width: usize,
 ) {
-
-    let (bb_min_x, bb_min_y, bb_max_x, bb_max_y) = bounding_box(triangle);
-
-    for y in bb_min_y..(bb_max_y) {
-        let line = line::LineIterator::new(bb_min_x, y, bb_max_x, y);
-        for point in line.filter(|p| point_in_triangle(*p, triangle)) {
-            buffer[utils::xy(point.0, point.1, buffer_width)] = color.bgra();
-        }
+    for point in TriangleIterator::new(triangle) {
+        buffer[utils::xy(point.0, point.1, buffer_width)] = color.bgra();
     }
 }
 
 
-pub struct TriangleIterator<'a> {
-    bb_min_x: u32,
-    bb_max_x: u32,
-    bb_max_y: u32,
-    triangle: &'a [Vector2<u32>],
+/// Iterator over pixels covered by triangle, with each next item being next pixel in it.
+/// Vertex positions are taken as pixel corners, so triangle `(0, 0), (0, h), (w, h)` covers
+/// half of `w` by `h` buffer.
+pub struct TriangleIterator {
+    setup: Option<TriangleSetup>,
+    x: u32,
     y: u32,
 }
 
-impl<'a> TriangleIterator<'a> {
-    pub fn new(triangle: &'a [Vector2<u32>]) -> TriangleIterator {
-        let (bb_min_x, bb_min_y, bb_max_x, bb_max_y) = bounding_box(triangle);
+impl TriangleIterator {
+    pub fn new(triangle: &[Vector2<u32>]) -> TriangleIterator {
+        let positions: Vec<Vector3<f32>> = triangle
+            .iter()
+            .map(|p| Vector3::new(p.x as f32, p.y as f32, 0.0))
+            .collect();
+        let setup = TriangleSetup::new(&positions, DEFAULT_SUBPIXEL_BITS, (0, 0, !0, !0));
+        let (x, y) = match setup {
+            Some(ref s) => (s.bounding_box.0, s.bounding_box.1),
+            None => (0, 0),
+        };
         TriangleIterator {
-            bb_min_x: bb_min_x,
-            bb_max_x: bb_max_x,
-            bb_max_y: bb_max_y,
-            triangle: triangle,
-            y: bb_min_y,
+            setup: setup,
+            x: x,
+            y: y,
         }
     }
 }
 
-impl<'a> Iterator for TriangleIterator<'a> {
-    type Item = Vec<(usize, usize)>;
+impl Iterator for TriangleIterator {
+    type Item = (usize, usize);
 
-    fn next(&mut self) -> Option<Vec<(usize, usize)>> {
-        if self.y > self.bb_max_y {
-            return None;
+    fn next(&mut self) -> Option<(usize, usize)> {
+        let setup = match self.setup {
+            Some(ref s) => s,
+            None => return None,
+        };
+        let (min_x, _, max_x, max_y) = setup.bounding_box;
+        while self.y <= max_y {
+            let (x, y) = (self.x, self.y);
+            if self.x < max_x {
+                self.x += 1;
+            } else {
+                self.x = min_x;
+                self.y += 1;
+            }
+            if setup.coverage(x, y).is_some() {
+                return Some((x as usize, y as usize));
+            }
         }
-        self.y += 1;
-        Some(
-            line::LineIterator::new(self.bb_min_x, self.y, self.bb_max_x, self.y)
-                .filter(|p| point_in_triangle(*p, self.triangle))
-                .collect(),
-        )
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 32;
+
+    /// Split rectangle into grid of `cells` by `cells` quads made of two triangles each, with
+    /// inner grid points moved by `jitter`, and count how many times each pixel gets covered.
+    fn tessellated_quad_coverage(
+        min: (f32, f32),
+        max: (f32, f32),
+        cells: usize,
+        jitter: f32,
+    ) -> Vec<u32> {
+        let mut points: Vec<Vector3<f32>> = Vec::new();
+        for j in 0..(cells + 1) {
+            for i in 0..(cells + 1) {
+                let mut x = min.0 + (max.0 - min.0) * i as f32 / cells as f32;
+                let mut y = min.1 + (max.1 - min.1) * j as f32 / cells as f32;
+                if i > 0 && i < cells && j > 0 && j < cells {
+                    x += jitter * ((i * 7 + j * 3) as f32).sin();
+                    y += jitter * ((i * 5 + j * 11) as f32).cos();
+                }
+                points.push(Vector3::new(x, y, 0.0));
+            }
+        }
+
+        let mut coverage: Vec<u32> = vec![0; (SIZE * SIZE) as usize];
+        let idx = |i: usize, j: usize| i + j * (cells + 1);
+        for j in 0..cells {
+            for i in 0..cells {
+                let (p00, p10) = (points[idx(i, j)], points[idx(i + 1, j)]);
+                let (p01, p11) = (points[idx(i, j + 1)], points[idx(i + 1, j + 1)]);
+                // Alternate diagonals and winding, so every edge orientation is exercised.
+                let triangles = if (i + j) % 2 == 0 {
+                    [[p00, p10, p11], [p00, p01, p11]]
+                } else {
+                    [[p10, p01, p00], [p10, p11, p01]]
+                };
+                for tri in &triangles {
+                    let setup = match TriangleSetup::new(
+                        tri,
+                        DEFAULT_SUBPIXEL_BITS,
+                        (0, 0, SIZE - 1, SIZE - 1),
+                    ) {
+                        Some(s) => s,
+                        None => continue,
+                    };
+                    let (min_x, min_y, max_x, max_y) = setup.bounding_box;
+                    for y in min_y..(max_y + 1) {
+                        for x in min_x..(max_x + 1) {
+                            if setup.coverage(x, y).is_some() {
+                                coverage[utils::xy(x as usize, y as usize, SIZE as usize)] += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        coverage
+    }
+
+    fn assert_covered_once(coverage: &[u32], min: (f32, f32), max: (f32, f32)) {
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+                // Top-left rule includes the left and top edges of the quad only.
+                let inside = cx >= min.0 && cx < max.0 && cy >= min.1 && cy < max.1;
+                let expected = if inside { 1 } else { 0 };
+                assert_eq!(
+                    coverage[utils::xy(x as usize, y as usize, SIZE as usize)],
+                    expected,
+                    "pixel ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_watertight_jittered_quad() {
+        let (min, max) = ((1.3, 2.1), (29.7, 30.2));
+        let coverage = tessellated_quad_coverage(min, max, 6, 1.7);
+        assert_covered_once(&coverage, min, max);
+    }
+
+    #[test]
+    fn test_watertight_pixel_center_edges() {
+        // Every grid edge goes exactly through pixel centers, so all of them are decided by
+        // the fill rule alone.
+        let (min, max) = ((0.5, 0.5), (24.5, 24.5));
+        let coverage = tessellated_quad_coverage(min, max, 8, 0.0);
+        assert_covered_once(&coverage, min, max);
+    }
+
+    #[test]
+    fn test_barycentric_coverage() {
+        let tri = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(8.0, 0.0, 0.0),
+            Vector3::new(0.0, 8.0, 0.0),
+        ];
+        let setup = TriangleSetup::new(&tri, DEFAULT_SUBPIXEL_BITS, (0, 0, 15, 15)).unwrap();
+        let bary = setup.coverage(0, 0).unwrap();
+        assert!((bary.x - 0.875).abs() < 1e-6);
+        assert!((bary.y - 0.0625).abs() < 1e-6);
+        assert!((bary.z - 0.0625).abs() < 1e-6);
+        assert!(setup.coverage(4, 4).is_none());
+    }
+
+    #[test]
+    fn test_sample_coverage() {
+        // Edge goes through the center of pixel (2, 0), so only samples on its left are covered.
+        let tri = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(2.5, 0.0, 0.0),
+            Vector3::new(2.5, 4.0, 0.0),
+        ];
+        let samples = sample_pattern(4);
+        let setup = TriangleSetup::with_samples(&tri, DEFAULT_SUBPIXEL_BITS, (0, 0, 15, 15), samples)
+            .unwrap();
+        let covered: Vec<bool> = samples
+            .iter()
+            .map(|&s| setup.sample_coverage(2, 0, s).is_some())
+            .collect();
+        assert_eq!(covered, vec![true, false, true, false]);
+        let bary = setup.barycentric(0, 3);
+        assert!((bary.x + bary.y + bary.z - 1.0).abs() < 1e-6);
+        assert!(bary.y < 0.0);
+    }
+
+    #[test]
+    fn test_max_subpixel_bits() {
+        assert_eq!(max_subpixel_bits(16383), MAX_SUBPIXEL_BITS);
+        assert_eq!(max_subpixel_bits(16384), MAX_SUBPIXEL_BITS - 1);
+        assert_eq!(max_subpixel_bits(1 << 28), 1);
+        assert_eq!(max_subpixel_bits(1 << 29), 0);
+    }
+
+    #[test]
+    fn test_sample_pattern_counts() {
+        assert_eq!(sample_pattern(0).len(), 1);
+        assert_eq!(sample_pattern(3).len(), 2);
+        assert_eq!(sample_pattern(4).len(), 4);
+        assert_eq!(sample_pattern(16).len(), 8);
     }
 }
@@ -1,7 +1,8 @@
+use std::io;
 use std::path::Path;
-use image::{self, Pixel, GenericImage};
+use image;
 use cgmath::*;
-use color;
+use texture;
 
 #[inline]
 pub fn reflect(i: Vector3<f32>, n: Vector3<f32>) -> Vector3<f32> {
@@ -9,12 +10,12 @@ pub fn reflect(i: Vector3<f32>, n: Vector3<f32>) -> Vector3<f32> {
 }
 
 
+/// Nearest texel of the full size level of `texture`, see `texture::Sampler` for filtering.
 #[inline]
-pub fn sample(texture: &image::DynamicImage, texcoord: Vector2<f32>) -> Vector4<f32> {
-    let (texwidth, texheight) = texture.dimensions();
+pub fn sample(texture: &texture::Texture, texcoord: Vector2<f32>) -> Vector4<f32> {
+    let (texwidth, texheight) = texture.dimensions(0);
     let (tx, ty) = texcoord_to_image_space(texcoord.x, texcoord.y, texwidth, texheight);
-    let t_clr = color::tup8_as_ranges(texture.get_pixel(tx, ty).channels4());
-    Vector4::new(t_clr.0, t_clr.1, t_clr.2, t_clr.3)
+    texture.texel(0, tx, ty).0
 }
 
 
@@ -101,9 +102,20 @@ pub fn tup8_to_32(input: (u8, u8, u8, u8)) -> u32 {
     ((input.0 as u32) << 24) | ((input.1 as u32) << 16) | ((input.2 as u32) << 8) | (input.3 as u32)
 }
 
-pub fn save_buffer_as_image(path: &Path, buffer: &[u32], width: u32, height: u32) {
+pub fn save_buffer_as_image(
+    path: &Path,
+    buffer: &[u32],
+    width: u32,
+    height: u32,
+) -> io::Result<()> {
     let clrtype = image::ColorType::RGBA(8);
-    image::save_buffer(path, bf_to_image(buffer).as_ref(), width, height, clrtype).unwrap();
+    image::save_buffer(path, bf_to_image(buffer).as_ref(), width, height, clrtype)
+}
+
+/// Convert buffer data `bf` with 32bit BGRA values to image with RGBA order of colors.
+pub fn buffer_to_image(bf: &[u32], width: u32, height: u32) -> image::RgbaImage {
+    image::RgbaImage::from_raw(width, height, bf_to_image(bf))
+        .expect("buffer size doesn't match image dimensions")
 }
 
 /// Convert screen (-1 to 1) coordinates to image space (0 - screen size) based on image
@@ -126,22 +138,14 @@ pub fn screen_to_image_space(x: f32, y: f32, width: u32, height: u32) -> (u32, u
 }
 
 /// Convert texcoord (0 to 1) coordinates to image space (0 - screen size) based on image
-/// width and height.
+/// width and height. Texcoords outside of the range are clamped to it and NaN is treated as 0.
 pub fn texcoord_to_image_space(x: f32, y: f32, width: u32, height: u32) -> (u32, u32) {
-    assert!(
-        x <= 1.0 && x >= 0.0,
-        "x value: {}, is not a valid texture coordinate",
-        x
-    );
-    assert!(
-        y <= 1.0 && y >= 0.0,
-        "y value: {}, is not a valid texture coordinate",
-        y
-    );
-    ((
+    let texcoord = |v: f32| if v.is_nan() { 0.0 } else { saturate(v) };
+    let (x, y) = (texcoord(x), texcoord(y));
+    (
         (x * (width - 1) as f32) as u32,
-        ((y * (height - 1) as f32) as u32),
-    ))
+        (y * (height - 1) as f32) as u32,
+    )
 }
 
 
@@ -156,6 +160,13 @@ mod tests {
         assert_eq!(screen_to_image_space(-1.0, 0.0, width, height), (0, 31));
         assert_eq!(screen_to_image_space(0.0, 1.0, width, height), (31, 63));
     }
+    #[test]
+    fn test_texcoord_to_image_space() {
+        assert_eq!(texcoord_to_image_space(0.5, 1.0, 65, 65), (32, 64));
+        assert_eq!(texcoord_to_image_space(-0.5, 2.0, 65, 65), (0, 64));
+        assert_eq!(texcoord_to_image_space(::std::f32::NAN, 0.0, 65, 65), (0, 0));
+    }
+
     #[test]
     fn test_clamp() {
         assert!(clamp(5.0, 0.0, 1.0) <= 1.0);
@@ -0,0 +1,152 @@
+//! Values passed from vertex shader to pixel shader.
+//!
+//! Anything vertex shader outputs besides the clip space position has to implement `Varying`,
+//! so the pipeline can create new vertices when clipping and interpolate the values across
+//! rasterized triangles.
+use cgmath::*;
+
+
+pub trait Varying: Copy + Send + Sync {
+    /// Weighted sum of three values, `a * weights.x + b * weights.y + c * weights.z`.
+    fn interpolate(a: &Self, b: &Self, c: &Self, weights: Vector3<f32>) -> Self;
+
+    /// Weighted sum like `interpolate`, given also `screen_weights` of the same point which are
+    /// linear in screen space. Used for rasterized pixels and vertices created by clipping,
+    /// takes `weights` unless overridden, see `NoPerspective`.
+    #[inline]
+    fn interpolate_screen(
+        a: &Self,
+        b: &Self,
+        c: &Self,
+        weights: Vector3<f32>,
+        _screen_weights: Vector3<f32>,
+    ) -> Self {
+        Self::interpolate(a, b, c, weights)
+    }
+
+    /// Linear interpolation between `a` and `b`.
+    fn lerp(a: &Self, b: &Self, t: f32) -> Self {
+        Self::interpolate(a, b, a, Vector3::new(1.0 - t, t, 0.0))
+    }
+
+    /// Difference `b - a`.
+    fn difference(a: &Self, b: &Self) -> Self {
+        Self::interpolate(a, b, a, Vector3::new(-1.0, 1.0, 0.0))
+    }
+}
+
+impl Varying for () {
+    fn interpolate(_: &(), _: &(), _: &(), _: Vector3<f32>) {}
+}
+
+impl Varying for f32 {
+    #[inline]
+    fn interpolate(a: &f32, b: &f32, c: &f32, weights: Vector3<f32>) -> f32 {
+        a * weights.x + b * weights.y + c * weights.z
+    }
+}
+
+macro_rules! impl_varying_vector {
+    ($VectorN:ident) => {
+        impl Varying for $VectorN<f32> {
+            #[inline]
+            fn interpolate(
+                a: &$VectorN<f32>,
+                b: &$VectorN<f32>,
+                c: &$VectorN<f32>,
+                weights: Vector3<f32>,
+            ) -> $VectorN<f32> {
+                *a * weights.x + *b * weights.y + *c * weights.z
+            }
+        }
+    }
+}
+
+impl_varying_vector!(Vector2);
+impl_varying_vector!(Vector3);
+impl_varying_vector!(Vector4);
+
+/// Varying interpolated linearly in screen space even when the draw interpolates the others
+/// with perspective, like the `noperspective` qualifier of GLSL.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct NoPerspective<T>(pub T);
+
+impl<T: Varying> Varying for NoPerspective<T> {
+    #[inline]
+    fn interpolate(a: &Self, b: &Self, c: &Self, weights: Vector3<f32>) -> Self {
+        NoPerspective(T::interpolate(&a.0, &b.0, &c.0, weights))
+    }
+
+    #[inline]
+    fn interpolate_screen(
+        a: &Self,
+        b: &Self,
+        c: &Self,
+        _weights: Vector3<f32>,
+        screen_weights: Vector3<f32>,
+    ) -> Self {
+        NoPerspective(T::interpolate(&a.0, &b.0, &c.0, screen_weights))
+    }
+}
+
+macro_rules! impl_varying_tuple {
+    ($($T:ident: $i:tt),+) => {
+        impl<$($T: Varying),+> Varying for ($($T,)+) {
+            #[inline]
+            fn interpolate(a: &Self, b: &Self, c: &Self, weights: Vector3<f32>) -> Self {
+                ($($T::interpolate(&a.$i, &b.$i, &c.$i, weights),)+)
+            }
+
+            #[inline]
+            fn interpolate_screen(
+                a: &Self,
+                b: &Self,
+                c: &Self,
+                weights: Vector3<f32>,
+                screen_weights: Vector3<f32>,
+            ) -> Self {
+                ($($T::interpolate_screen(&a.$i, &b.$i, &c.$i, weights, screen_weights),)+)
+            }
+        }
+    }
+}
+
+impl_varying_tuple!(A: 0);
+impl_varying_tuple!(A: 0, B: 1);
+impl_varying_tuple!(A: 0, B: 1, C: 2);
+impl_varying_tuple!(A: 0, B: 1, C: 2, D: 3);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate_tuple() {
+        let a = (1.0, Vector2::new(0.0, 0.0));
+        let b = (3.0, Vector2::new(2.0, 4.0));
+        let c = (5.0, Vector2::new(4.0, 8.0));
+        let v = Varying::interpolate(&a, &b, &c, Vector3::new(0.25, 0.5, 0.25));
+        assert_eq!(v.0, 3.0);
+        assert_eq!(v.1, Vector2::new(2.0, 4.0));
+
+        let l: (f32, Vector2<f32>) = Varying::lerp(&a, &b, 0.5);
+        assert_eq!(l.0, 2.0);
+
+        let d: (f32, Vector2<f32>) = Varying::difference(&a, &b);
+        assert_eq!(d, (2.0, Vector2::new(2.0, 4.0)));
+    }
+
+    #[test]
+    fn test_no_perspective() {
+        let a = (0.0, NoPerspective(0.0));
+        let b = (1.0, NoPerspective(1.0));
+        let c = a;
+        let weights = Vector3::new(0.0, 0.25, 0.75);
+        let screen_weights = Vector3::new(0.0, 0.5, 0.5);
+        let v = Varying::interpolate_screen(&a, &b, &c, weights, screen_weights);
+        assert_eq!(v, (0.25, NoPerspective(0.5)));
+        // Without screen space weights both use the same ones.
+        assert_eq!(Varying::lerp(&a, &b, 0.25), (0.25, NoPerspective(0.25)));
+    }
+}